@@ -0,0 +1,188 @@
+//! Compares the decoders against their previous implementations, on
+//! synthetic archives generated for the occasion:
+//! - tar.xz: peak memory and wall time against the decoder that decompressed
+//!   the whole archive into memory and a temporary file before unpacking it.
+//!   Memory allocated by liblzma itself is not counted, which is the same
+//!   for both decoders.
+//! - tar.gz: wall time against the decoder that read the archive unbuffered
+//!   and wrote every file from a single thread, both on an archive with a few
+//!   big files and on one with thousands of small files.
+//!
+//! Run it with `cargo bench --bench decoders`, adding `--features fast-inflate`
+//! to try the faster inflate backend. The size of the generated archives,
+//! in MiB, can be changed with `SAGE_BENCH_MB` (32 by default).
+
+extern crate flate2;
+extern crate sage;
+extern crate tar;
+extern crate tempfile;
+extern crate xz2;
+
+use sage::{Decoder, TarGzDecoder, TarXzDecoder};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Allocator that keeps track of the highest amount of memory in use
+struct CountingAllocator;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The tar.xz decoder as it was before streaming the archive
+fn buffered_decode(file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let xz_file = File::open(file_name)?;
+    let mut decompressed = xz2::read::XzDecoder::new(xz_file);
+    let mut buf: Vec<u8> = Vec::new();
+    decompressed.read_to_end(&mut buf)?;
+    let mut tar_file = tempfile::NamedTempFile::new()?;
+    tar_file.write_all(buf.as_mut_slice())?;
+    let tar_file_2 = tar_file.reopen()?;
+    let mut archive = tar::Archive::new(tar_file_2);
+    archive.unpack(unpack_dir)?;
+
+    Ok(())
+}
+
+/// The tar.gz decoder as it was before buffering and parallel writes
+fn unbuffered_decode(file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let gz_file = File::open(file_name)?;
+    let tar = flate2::read::GzDecoder::new(gz_file);
+    let mut archive = tar::Archive::new(tar);
+    archive.unpack(unpack_dir)?;
+
+    Ok(())
+}
+
+/// Appends `mib` MiB of somewhat compressible files of `file_size` bytes each
+fn append_files<W: Write>(
+    builder: &mut tar::Builder<W>,
+    mib: usize,
+    file_size: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut state: u32 = 0x2545_f491;
+    for i in 0..mib * 1024 * 1024 / file_size {
+        // text-like data, so there is something to compress
+        let contents: Vec<u8> = (0..file_size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b"abcdefghijklmnop \n"[(state % 18) as usize]
+            })
+            .collect();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let path = format!("sdk/lib/{}/part{}.txt", i / 256, i);
+        builder.append_data(&mut header, path, &contents[..])?;
+    }
+
+    Ok(())
+}
+
+/// Writes a tar.xz archive with `mib` MiB of 1 MiB files
+fn build_xz_archive(path: &Path, mib: usize) -> Result<(), Box<dyn Error>> {
+    let xz = xz2::write::XzEncoder::new(File::create(path)?, 1);
+    let mut builder = tar::Builder::new(xz);
+    append_files(&mut builder, mib, 1024 * 1024)?;
+    builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Writes a tar.gz archive with `mib` MiB of files of `file_size` bytes each
+fn build_gz_archive(path: &Path, mib: usize, file_size: usize) -> Result<(), Box<dyn Error>> {
+    let gz = flate2::write::GzEncoder::new(File::create(path)?, flate2::Compression::fast());
+    let mut builder = tar::Builder::new(gz);
+    append_files(&mut builder, mib, file_size)?;
+    builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Runs the decoder, returning its wall time and the peak of memory it used
+fn measure<F>(decode: F) -> (Duration, usize)
+where
+    F: FnOnce() -> Result<(), Box<dyn Error>>,
+{
+    let baseline = IN_USE.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let start = Instant::now();
+    decode().expect("decoding failed");
+    let elapsed = start.elapsed();
+
+    (elapsed, PEAK.load(Ordering::SeqCst) - baseline)
+}
+
+fn main() {
+    let mib: usize = env::var("SAGE_BENCH_MB")
+        .ok()
+        .and_then(|mib| mib.parse().ok())
+        .unwrap_or(32);
+    let dir = tempfile::tempdir().unwrap();
+
+    let archive = dir.path().join("sdk.tar.xz");
+    build_xz_archive(&archive, mib).unwrap();
+    let archive = archive.to_str().unwrap();
+    let buffered_dir = dir.path().join("buffered");
+    let (buffered_time, buffered_peak) = measure(|| buffered_decode(archive, &buffered_dir));
+    let streaming_dir = dir.path().join("streaming");
+    let (streaming_time, streaming_peak) =
+        measure(|| TarXzDecoder::new().decode(archive, &streaming_dir));
+
+    println!("tar.xz decoding of a {} MiB archive", mib);
+    println!("{:<12}{:>14}{:>18}", "DECODER", "WALL TIME", "PEAK MEMORY");
+    for (name, time, peak) in &[
+        ("buffered", buffered_time, buffered_peak),
+        ("streaming", streaming_time, streaming_peak),
+    ] {
+        println!("{:<12}{:>12.2?}{:>14} KiB", name, time, peak / 1024);
+    }
+
+    for (label, file_size) in &[("1 MiB", 1024 * 1024), ("4 KiB", 4 * 1024)] {
+        let archive = dir.path().join(format!("sdk-{}.tar.gz", file_size));
+        build_gz_archive(&archive, mib, *file_size).unwrap();
+        let archive = archive.to_str().unwrap();
+        let unbuffered_dir = dir.path().join(format!("unbuffered-{}", file_size));
+        let (unbuffered_time, _) = measure(|| unbuffered_decode(archive, &unbuffered_dir));
+        let parallel_dir = dir.path().join(format!("parallel-{}", file_size));
+        let (parallel_time, _) = measure(|| TarGzDecoder::new().decode(archive, &parallel_dir));
+
+        println!();
+        println!(
+            "tar.gz decoding of a {} MiB archive of {} files",
+            mib, label
+        );
+        println!("{:<12}{:>14}", "DECODER", "WALL TIME");
+        for (name, time) in &[("unbuffered", unbuffered_time), ("parallel", parallel_time)] {
+            println!("{:<12}{:>12.2?}", name, time);
+        }
+    }
+}
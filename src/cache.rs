@@ -0,0 +1,133 @@
+use crate::install_utils::walk_files;
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Amount of seconds that a cached file is considered fresh when
+/// `SAGE_CACHE_TTL` is not set
+pub const DEFAULT_CACHE_TTL: u64 = 60 * 60;
+
+/// On-disk cache of the files fetched from a repository.
+/// Files are stored with the same relative path they have in the repository,
+/// and they are considered fresh until they are older than the time to live.
+pub struct Cache {
+    dir: PathBuf,
+    ttl: Duration,
+    offline: bool,
+}
+
+impl Cache {
+    /// Creates a new `Cache` stored in the given directory.
+    /// When working offline, cached files never go stale.
+    pub fn new(dir: &Path, ttl: Duration, offline: bool) -> Cache {
+        Cache {
+            dir: dir.to_path_buf(),
+            ttl,
+            offline,
+        }
+    }
+
+    /// Returns true if nothing can be fetched from the network
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the contents of the cached file, as long as it is still fresh
+    pub fn get(&self, key: &str) -> Option<String> {
+        let path = self.dir.join(key);
+        let age = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default();
+        if !self.offline && age > self.ttl {
+            return None;
+        }
+
+        fs::read_to_string(path).ok()
+    }
+
+    /// Stores the contents of a file in the cache, replacing the previous ones
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::write` and `std::fs::rename`.
+    pub fn put(&self, key: &str, contents: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.dir.join(key);
+        fs::create_dir_all(path.parent().unwrap())?;
+        // write to a temporary file first so a crash never leaves a half-written file
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    /// Removes a file from the cache, if it is there
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::remove_file`.
+    pub fn remove(&self, key: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.dir.join(key);
+        if path.is_file() {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the key of every cached file, fresh or not
+    ///
+    /// # Errors
+    /// Check out the documentation for `walk_files`.
+    pub fn keys(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut keys: Vec<String> = walk_files(&self.dir)?
+            .iter()
+            .filter_map(|file| file.strip_prefix(&self.dir).ok())
+            .map(|file| file.to_string_lossy().replace('\\', "/"))
+            .filter(|key| !key.ends_with(".tmp"))
+            .collect();
+        keys.sort();
+
+        Ok(keys)
+    }
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_files_are_served() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), Duration::from_secs(60), false);
+        assert!(cache.get("linux/node/metadata.yml").is_none());
+
+        cache.put("linux/node/metadata.yml", "name: node").unwrap();
+        assert_eq!(cache.get("linux/node/metadata.yml").unwrap(), "name: node");
+        assert_eq!(cache.keys().unwrap(), vec!["linux/node/metadata.yml"]);
+
+        cache.remove("linux/node/metadata.yml").unwrap();
+        assert!(cache.keys().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stale_files_are_only_served_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let online = Cache::new(dir.path(), Duration::from_secs(0), false);
+        online.put("linux/node/metadata.yml", "name: node").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(online.get("linux/node/metadata.yml").is_none());
+
+        let offline = Cache::new(dir.path(), Duration::from_secs(0), true);
+        assert_eq!(
+            offline.get("linux/node/metadata.yml").unwrap(),
+            "name: node"
+        );
+    }
+}
@@ -0,0 +1,433 @@
+extern crate chrono;
+extern crate yaml_rust;
+
+use crate::errors::CorruptedDatabaseError;
+use crate::install_utils::Variant;
+
+use yaml_rust::yaml::{Array, Hash};
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Record of a package that has been successfully installed by **sage**
+pub struct InstalledPackage {
+    name: String,
+    version: String,
+    repo: String,
+    install_type: String,
+    install_target: String,
+    installed_at: String,
+    files: Vec<String>, // relative to the programs directory
+    variant: Variant,
+}
+
+/// Local database that keeps track of every installed package.
+/// Each version of a package is stored as its own yaml file, inside
+/// of a directory named after the package, like *db/python/3.8.0.yml*.
+pub struct Database {
+    dir: PathBuf,
+}
+
+// --------- IMPLEMENTATIONS ---------
+
+impl InstalledPackage {
+    /// Creates a new record for a package that has just been installed,
+    /// timestamping it with the current local time
+    pub fn new(
+        name: &str,
+        version: &str,
+        repo: &str,
+        install_type: &str,
+        install_target: &str,
+        files: Vec<String>,
+    ) -> InstalledPackage {
+        InstalledPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            repo: repo.to_string(),
+            install_type: install_type.to_string(),
+            install_target: install_target.to_string(),
+            installed_at: chrono::Local::now().to_rfc3339(),
+            files,
+            variant: Variant::default(),
+        }
+    }
+
+    /// Sets the build options that were explicitly enabled or disabled
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    /// Reads a record back from its yaml representation
+    ///
+    /// # Errors
+    /// The function will return an error if the string is not valid yaml
+    /// or if any of the expected fields is missing. Records without
+    /// build options, like the ones written by older versions, keep the defaults.
+    pub fn from_yaml(str: &str) -> Result<InstalledPackage, Box<dyn Error>> {
+        let docs = YamlLoader::load_from_str(str)?;
+        let doc = match docs.first() {
+            Some(doc) => doc,
+            None => return Err(Box::new(CorruptedDatabaseError)),
+        };
+
+        let field = |key: &str| match doc[key].as_str() {
+            Some(value) => Ok(value.to_string()),
+            None => Err(CorruptedDatabaseError),
+        };
+        let files = match doc["files"].as_vec() {
+            Some(vec) => vec
+                .iter()
+                .map(|file| file.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>(),
+            None => None,
+        };
+        let files = match files {
+            Some(files) => files,
+            None => return Err(Box::new(CorruptedDatabaseError)),
+        };
+        let options = |key: &str| match doc[key].as_vec() {
+            Some(vec) => vec
+                .iter()
+                .filter_map(|option| option.as_str().map(String::from))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Ok(InstalledPackage {
+            name: field("name")?,
+            version: field("version")?,
+            repo: field("repo")?,
+            install_type: field("install_type")?,
+            install_target: field("install_target")?,
+            installed_at: field("installed_at")?,
+            files,
+            variant: Variant {
+                with: options("with"),
+                without: options("without"),
+            },
+        })
+    }
+
+    /// Returns the yaml representation of the record
+    pub fn to_yaml(&self) -> Result<String, Box<dyn Error>> {
+        let mut doc = Hash::new();
+        let mut insert = |key: &str, value: &str| {
+            doc.insert(
+                Yaml::String(key.to_string()),
+                Yaml::String(value.to_string()),
+            );
+        };
+        insert("name", &self.name);
+        insert("version", &self.version);
+        insert("repo", &self.repo);
+        insert("install_type", &self.install_type);
+        insert("install_target", &self.install_target);
+        insert("installed_at", &self.installed_at);
+        let files: Array = self.files.iter().cloned().map(Yaml::String).collect();
+        doc.insert(Yaml::String(String::from("files")), Yaml::Array(files));
+        let with: Array = self
+            .variant
+            .with
+            .iter()
+            .cloned()
+            .map(Yaml::String)
+            .collect();
+        doc.insert(Yaml::String(String::from("with")), Yaml::Array(with));
+        let without: Array = self
+            .variant
+            .without
+            .iter()
+            .cloned()
+            .map(Yaml::String)
+            .collect();
+        doc.insert(Yaml::String(String::from("without")), Yaml::Array(without));
+
+        let mut out = String::new();
+        YamlEmitter::new(&mut out).dump(&Yaml::Hash(doc))?;
+        out.push('\n');
+
+        Ok(out)
+    }
+
+    /// Returns the name of the installed package
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the installed version of the package
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+
+    /// Returns the name of the repository the package was installed from
+    pub fn get_repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Returns the installation procedure that was used, like make or bin
+    pub fn get_install_type(&self) -> &str {
+        &self.install_type
+    }
+
+    /// Returns the target the installation procedure was applied to
+    pub fn get_install_target(&self) -> &str {
+        &self.install_target
+    }
+
+    /// Returns the moment of the installation, formatted as RFC 3339
+    pub fn get_installed_at(&self) -> &str {
+        &self.installed_at
+    }
+
+    /// Returns every file placed by the package, relative to the programs directory
+    pub fn get_files(&self) -> &Vec<String> {
+        &self.files
+    }
+
+    /// Returns the build options that were explicitly enabled or disabled
+    pub fn get_variant(&self) -> &Variant {
+        &self.variant
+    }
+
+    /// Returns the amount of bytes that the files of the package
+    /// currently take up inside of the programs directory
+    pub fn get_disk_usage(&self, install_dir: &Path) -> u64 {
+        self.files
+            .iter()
+            .filter_map(|file| fs::symlink_metadata(install_dir.join(file)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+}
+
+impl Database {
+    /// Opens the database stored in the given directory
+    pub fn new(dir: &Path) -> Database {
+        Database {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Stores the record of an installed package, replacing any
+    /// previous record for the same name and version.
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::write` and `std::fs::rename`
+    /// to see the situations in which this method will return an error.
+    pub fn add(&self, pkg: &InstalledPackage) -> Result<(), Box<dyn Error>> {
+        let path = self.record_path(pkg.get_name(), pkg.get_version());
+        fs::create_dir_all(path.parent().unwrap())?;
+        // write to a temporary file first so a crash never leaves a half-written record
+        let tmp_path = path.with_extension("yml.tmp");
+        fs::write(&tmp_path, pkg.to_yaml()?)?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    /// Returns the record of the specified version of a package, if it is installed
+    ///
+    /// # Errors
+    /// The function will return an error if the record exists but cannot be read.
+    pub fn get(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> Result<Option<InstalledPackage>, Box<dyn Error>> {
+        let path = self.record_path(name, version);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        Ok(Some(read_record(&path)?))
+    }
+
+    /// Returns the record of every installed package, sorted by name and version.
+    /// Records that can't be read are skipped with a warning, so that a single
+    /// broken one doesn't keep every other package from being managed.
+    ///
+    /// # Errors
+    /// The function will return an error if the database directory cannot be read.
+    pub fn list(&self) -> Result<Vec<InstalledPackage>, Box<dyn Error>> {
+        let mut pkgs = Vec::new();
+        if !self.dir.is_dir() {
+            return Ok(pkgs);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let pkg_dir = entry?.path();
+            if !pkg_dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&pkg_dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("yml") {
+                    continue;
+                }
+                match read_record(&path) {
+                    Ok(pkg) => pkgs.push(pkg),
+                    Err(e) => eprintln!(
+                        "Warning: the record {} can't be read, so it is skipped: {}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+        }
+        pkgs.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+        Ok(pkgs)
+    }
+
+    /// Returns the record of every installed version of a package
+    ///
+    /// # Errors
+    /// Check out the documentation for `Database::list`.
+    pub fn versions_of(&self, name: &str) -> Result<Vec<InstalledPackage>, Box<dyn Error>> {
+        let pkgs = self.list()?;
+
+        Ok(pkgs.into_iter().filter(|pkg| pkg.name == name).collect())
+    }
+
+    /// Deletes the record of the specified version of a package
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::remove_file`.
+    pub fn remove(&self, name: &str, version: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.record_path(name, version);
+        fs::remove_file(&path)?;
+        // the directory of the package goes away along with its last record
+        let _ = fs::remove_dir(path.parent().unwrap());
+
+        Ok(())
+    }
+
+    fn record_path(&self, name: &str, version: &str) -> PathBuf {
+        self.dir.join(name).join(format!("{}.yml", version))
+    }
+}
+
+/// Reads the record stored in the given file
+///
+/// # Errors
+/// Check out the documentation for `std::fs::read_to_string`
+/// and `InstalledPackage::from_yaml`.
+fn read_record(path: &Path) -> Result<InstalledPackage, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+
+    InstalledPackage::from_yaml(&contents)
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn python_record() -> InstalledPackage {
+        InstalledPackage::new(
+            "Python",
+            "3.8.0",
+            "Arcanum",
+            "make",
+            "Python-3.8.0",
+            vec![
+                String::from("Python-3.8.0/bin/python3"),
+                String::from("Python-3.8.0/lib/libpython3.8.a"),
+            ],
+        )
+    }
+
+    #[test]
+    fn record_survives_yaml_round_trip() {
+        let mut record = python_record();
+        record.set_variant(Variant {
+            with: vec![String::from("optimizations")],
+            without: vec![String::from("ssl")],
+        });
+        let parsed = InstalledPackage::from_yaml(&record.to_yaml().unwrap()).unwrap();
+
+        assert_eq!(parsed.get_name(), "Python");
+        assert_eq!(parsed.get_version(), "3.8.0");
+        assert_eq!(parsed.get_repo(), "Arcanum");
+        assert_eq!(parsed.get_install_type(), "make");
+        assert_eq!(parsed.get_install_target(), "Python-3.8.0");
+        assert_eq!(parsed.get_installed_at(), record.get_installed_at());
+        assert_eq!(parsed.get_files(), record.get_files());
+        assert_eq!(parsed.get_variant().with, vec!["optimizations"]);
+        assert_eq!(parsed.get_variant().without, vec!["ssl"]);
+    }
+
+    #[test]
+    fn database_stores_and_lists_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path());
+        db.add(&python_record()).unwrap();
+
+        let stored = db.get("Python", "3.8.0").unwrap().unwrap();
+        assert_eq!(stored.get_files().len(), 2);
+        assert!(stored.get_variant().with.is_empty());
+        assert!(db.get("Python", "3.7.0").unwrap().is_none());
+
+        let all = db.list().unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].get_name(), "Python");
+        assert_eq!(db.versions_of("Python").unwrap().len(), 1);
+        assert!(db.versions_of("node").unwrap().is_empty());
+
+        db.remove("Python", "3.8.0").unwrap();
+        assert!(db.list().unwrap().is_empty());
+        assert!(!dir.path().join("Python").exists());
+    }
+
+    #[test]
+    fn broken_records_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path());
+        db.add(&python_record()).unwrap();
+        fs::create_dir(dir.path().join("node")).unwrap();
+        fs::write(dir.path().join("node").join("12.0.yml"), "name: node").unwrap();
+
+        let all = db.list().unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].get_name(), "Python");
+        assert!(db.get("node", "12.0").is_err());
+    }
+
+    #[test]
+    fn records_of_similar_names_are_kept_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::new(dir.path());
+        db.add(&InstalledPackage::new(
+            "foo_1",
+            "2.0",
+            "Arcanum",
+            "bin",
+            "",
+            Vec::new(),
+        ))
+        .unwrap();
+        db.add(&InstalledPackage::new(
+            "foo",
+            "1_2.0",
+            "Arcanum",
+            "bin",
+            "",
+            Vec::new(),
+        ))
+        .unwrap();
+
+        assert_eq!(db.list().unwrap().len(), 2);
+        assert_eq!(db.get("foo", "1_2.0").unwrap().unwrap().get_name(), "foo");
+        assert_eq!(db.get("foo_1", "2.0").unwrap().unwrap().get_name(), "foo_1");
+    }
+
+    #[test]
+    fn incomplete_record_is_rejected() {
+        assert!(InstalledPackage::from_yaml("name: Python\nversion: 3.8.0").is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
 
 /// Error for when no versions of the specified package could be found
 #[derive(Debug)]
@@ -87,3 +88,443 @@ impl fmt::Display for PathNotFoundError {
         write!(f, "A path was Expected, but none was found.")
     }
 }
+
+/// Error for when a record of the installed-package database cannot be read
+#[derive(Debug)]
+pub struct CorruptedDatabaseError;
+
+impl Error for CorruptedDatabaseError {}
+
+impl fmt::Display for CorruptedDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The database of installed packages is corrupted.")
+    }
+}
+
+/// Error for when the file of a package lacks something that every package needs,
+/// like its version or its url
+#[derive(Debug)]
+pub struct CorruptedPackageError {
+    pub field: String,
+}
+
+impl Error for CorruptedPackageError {}
+
+impl fmt::Display for CorruptedPackageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The file of the package has no {}.", self.field)
+    }
+}
+
+/// Error for when the specified package is not installed
+#[derive(Debug)]
+pub struct PackageNotInstalledError;
+
+impl Error for PackageNotInstalledError {}
+
+impl fmt::Display for PackageNotInstalledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The specified package is not installed.")
+    }
+}
+
+/// Error for when the shell of the user is not supported
+#[derive(Debug)]
+pub struct ShellNotSupportedError;
+
+impl Error for ShellNotSupportedError {}
+
+impl fmt::Display for ShellNotSupportedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The shell is not supported. Please specify one of bash, zsh or fish."
+        )
+    }
+}
+
+/// Error for when a downloaded file doesn't match its expected checksum
+#[derive(Debug)]
+pub struct ChecksumMismatchError {
+    pub algorithm: &'static str,
+    pub expected: String,
+    pub found: String,
+}
+
+impl Error for ChecksumMismatchError {}
+
+impl fmt::Display for ChecksumMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The {} checksum of the downloaded file does not match the expected one \
+             (expected {}, found {}). The file has been deleted.",
+            self.algorithm, self.expected, self.found
+        )
+    }
+}
+
+/// Error for when a package provides no checksum to verify its download
+#[derive(Debug)]
+pub struct MissingChecksumError;
+
+impl Error for MissingChecksumError {}
+
+impl fmt::Display for MissingChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The package provides no checksum to verify its download. \
+             Use --insecure to download it anyway."
+        )
+    }
+}
+
+/// Error for when a file of a repository doesn't have a valid signature
+#[derive(Debug)]
+pub struct SignatureVerificationError;
+
+impl Error for SignatureVerificationError {}
+
+impl fmt::Display for SignatureVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The signature of the repository file could not be verified with any trusted key."
+        )
+    }
+}
+
+/// Error for when a repository with the same name is already configured
+#[derive(Debug)]
+pub struct RepoAlreadyExistsError;
+
+impl Error for RepoAlreadyExistsError {}
+
+impl fmt::Display for RepoAlreadyExistsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "A repository with the specified name already exists.")
+    }
+}
+
+/// Error for when the specified repository is not configured
+#[derive(Debug)]
+pub struct RepoNotFoundError;
+
+impl Error for RepoNotFoundError {}
+
+impl fmt::Display for RepoNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The specified repository could not be found.")
+    }
+}
+
+/// Error for when the repositories config file cannot be read
+#[derive(Debug)]
+pub struct CorruptedRepoConfigError;
+
+impl Error for CorruptedRepoConfigError {}
+
+impl fmt::Display for CorruptedRepoConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The repositories config file is not valid.")
+    }
+}
+
+/// Error for when the repo command is called with the wrong arguments
+#[derive(Debug)]
+pub struct InvalidRepoCommandError;
+
+impl Error for InvalidRepoCommandError {}
+
+impl fmt::Display for InvalidRepoCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Usage: sage repo list | sage repo add <name> <url> [--priority <n>] | sage repo remove <name>"
+        )
+    }
+}
+
+/// Error for when working offline and the requested file is not cached
+#[derive(Debug)]
+pub struct NotCachedError;
+
+impl Error for NotCachedError {}
+
+impl fmt::Display for NotCachedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The requested file is not cached, so it can't be used offline. Fetch it once while online first."
+        )
+    }
+}
+
+/// Error for when a version requirement can't be parsed
+#[derive(Debug)]
+pub struct InvalidVersionReqError;
+
+impl Error for InvalidVersionReqError {}
+
+impl fmt::Display for InvalidVersionReqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The specified version requirement is not valid.")
+    }
+}
+
+/// Error for when the dependencies of a package depend on each other in a loop
+#[derive(Debug)]
+pub struct DependencyCycleError {
+    pub cycle: Vec<String>,
+}
+
+impl Error for DependencyCycleError {}
+
+impl fmt::Display for DependencyCycleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The dependencies form a cycle: {}.",
+            self.cycle.join(" -> ")
+        )
+    }
+}
+
+/// Error for when no version of a package meets every requirement on it
+#[derive(Debug)]
+pub struct DependencyConflictError {
+    pub name: String,
+    pub requirements: Vec<String>,
+}
+
+impl Error for DependencyConflictError {}
+
+impl fmt::Display for DependencyConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No version of {} meets every requirement on it: {}.",
+            self.name,
+            self.requirements.join(", ")
+        )
+    }
+}
+
+/// Error for when an archive tries to place files outside of its unpack directory
+#[derive(Debug)]
+pub struct UnsafeArchivePathError;
+
+impl Error for UnsafeArchivePathError {}
+
+impl fmt::Display for UnsafeArchivePathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The archive tries to place files outside of the directory it is unpacked in."
+        )
+    }
+}
+
+/// Error for when the name given to a single-file binary is not a plain file name
+#[derive(Debug)]
+pub struct InvalidBinaryNameError;
+
+impl Error for InvalidBinaryNameError {}
+
+impl fmt::Display for InvalidBinaryNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The name of the binary must be a file name, not a path.")
+    }
+}
+
+/// Error for when the name or version of a package is a path instead of a plain name,
+/// like *../python*, which would lead outside of the directories of the repository
+#[derive(Debug)]
+pub struct InvalidPackageNameError;
+
+impl Error for InvalidPackageNameError {}
+
+impl fmt::Display for InvalidPackageNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The name and version of a package can't be empty, contain path separators or be '.' or '..'."
+        )
+    }
+}
+
+/// Error for when a step of a build from source fails,
+/// carrying the last lines that the build printed
+#[derive(Debug)]
+pub struct BuildError {
+    pub step: String,
+    pub status: Option<i32>, // None when the step couldn't be started or was killed
+    pub log: PathBuf,
+    pub tail: Vec<String>,
+}
+
+impl Error for BuildError {}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.status {
+            Some(code) => write!(f, "The {} step failed with exit code {}.", self.step, code)?,
+            None => write!(f, "The {} step could not be completed.", self.step)?,
+        }
+        write!(f, " The full log is at {}", self.log.display())?;
+        if !self.tail.is_empty() {
+            write!(f, ", and it ends with:")?;
+            for line in &self.tail {
+                write!(f, "\n    {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error for when a git package doesn't say which revision of its repository to use
+#[derive(Debug)]
+pub struct MissingRevisionError;
+
+impl Error for MissingRevisionError {}
+
+impl fmt::Display for MissingRevisionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The package doesn't say which tag or commit of its repository to use."
+        )
+    }
+}
+
+/// Error for when a cloned repository is not at the commit the package pins
+#[derive(Debug)]
+pub struct RevisionMismatchError {
+    pub expected: String,
+    pub found: String,
+}
+
+impl Error for RevisionMismatchError {}
+
+impl fmt::Display for RevisionMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The cloned repository is at commit {} instead of the expected {}.",
+            self.found, self.expected
+        )
+    }
+}
+
+/// Error for when a git command fails
+#[derive(Debug)]
+pub struct GitCommandError {
+    pub command: String,
+    pub stderr: String,
+}
+
+impl Error for GitCommandError {}
+
+impl fmt::Display for GitCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The command 'git {}' failed: {}",
+            self.command, self.stderr
+        )
+    }
+}
+
+/// Error for when the user picks a build option that the package doesn't have
+#[derive(Debug)]
+pub struct UnknownBuildOptionError {
+    pub name: String,
+    pub available: Vec<String>,
+}
+
+impl Error for UnknownBuildOptionError {}
+
+impl fmt::Display for UnknownBuildOptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.available.is_empty() {
+            write!(
+                f,
+                "The package has no build options, so {} can't be used.",
+                self.name
+            )
+        } else {
+            write!(
+                f,
+                "The package has no build option called {}. The available ones are: {}",
+                self.name,
+                self.available.join(", ")
+            )
+        }
+    }
+}
+
+/// Error for when the installation section of a package
+/// lacks something that is needed to install it, like its type or target
+#[derive(Debug)]
+pub struct MissingInstallInfoError {
+    pub field: String,
+}
+
+impl Error for MissingInstallInfoError {}
+
+impl fmt::Display for MissingInstallInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The installation section of the package has no {}, so it can't be installed.",
+            self.field
+        )
+    }
+}
+
+/// Error for when a yaml file of a repository holds no document, like an empty
+/// or truncated file
+#[derive(Debug)]
+pub struct EmptyDocumentError;
+
+impl Error for EmptyDocumentError {}
+
+impl fmt::Display for EmptyDocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The file of the repository is empty.")
+    }
+}
+
+/// Error for when a url has a scheme that sage can't fetch files from, like *ftp://*
+#[derive(Debug)]
+pub struct UnsupportedSchemeError {
+    pub scheme: String,
+}
+
+impl Error for UnsupportedSchemeError {}
+
+impl fmt::Display for UnsupportedSchemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The {}:// scheme is not supported, only http(s):// and file:// urls and local paths are.",
+            self.scheme
+        )
+    }
+}
+
+/// Error for when the name of a repository is not a plain directory name
+#[derive(Debug)]
+pub struct InvalidRepoNameError;
+
+impl Error for InvalidRepoNameError {}
+
+impl fmt::Display for InvalidRepoNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The name of a repository can't be empty, contain path separators or be '.' or '..'."
+        )
+    }
+}
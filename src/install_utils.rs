@@ -1,42 +1,231 @@
+extern crate bzip2;
+extern crate filetime;
 extern crate flate2;
 extern crate fs_extra;
 extern crate tar;
-extern crate tempfile;
 extern crate xz2;
+extern crate zip;
+extern crate zstd;
 
+use crate::errors::{
+    BuildError, GitCommandError, InvalidBinaryNameError, UnknownBuildOptionError,
+    UnsafeArchivePathError,
+};
+
+use bzip2::read::BzDecoder;
+use filetime::FileTime;
 use flate2::read::GzDecoder;
-use tar::Archive;
-use tempfile::NamedTempFile;
+use tar::{Archive, EntryType};
 use xz2::read::XzDecoder;
+use zip::ZipArchive;
 
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
-use std::process::Command;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+/// Files up to this size are handed to the writer threads while the archive
+/// keeps being decompressed, bigger ones are written right away
+const PARALLEL_WRITE_LIMIT: u64 = 1024 * 1024;
+/// Size of the read buffer for compressed archives
+const ARCHIVE_BUFFER_SIZE: usize = 256 * 1024;
+/// Amount of lines at the end of a build log shown when the build fails
+const LOG_TAIL_LINES: usize = 20;
 
 /// Enum containing every single supported installation type
 pub enum InstallTypes {
     MakeInstall,
+    CMake,
+    Meson,
+    Cargo,
+    Script, // the steps listed by the package
     Bin,
 }
 
+impl InstallTypes {
+    /// Returns the install type with the given name, like *make*
+    pub fn from_name(name: &str) -> Option<InstallTypes> {
+        match name {
+            "make" => Some(InstallTypes::MakeInstall),
+            "cmake" => Some(InstallTypes::CMake),
+            "meson" => Some(InstallTypes::Meson),
+            "cargo" => Some(InstallTypes::Cargo),
+            "script" => Some(InstallTypes::Script),
+            "bin" => Some(InstallTypes::Bin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for InstallTypes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstallTypes::MakeInstall => write!(f, "make"),
+            InstallTypes::CMake => write!(f, "cmake"),
+            InstallTypes::Meson => write!(f, "meson"),
+            InstallTypes::Cargo => write!(f, "cargo"),
+            InstallTypes::Script => write!(f, "script"),
+            InstallTypes::Bin => write!(f, "bin"),
+        }
+    }
+}
+
 /// Enum cotaining every single file type that can be downloaded
 pub enum FileTypes {
     TarXz,
     TarGz,
+    TarBz2,
+    TarZst,
+    Zip,
+    Git,     // a git repository, cloned at a pinned revision
+    Raw,     // a single executable, installed as it is
+    Unknown, // to be detected once the file has been downloaded
+}
+
+impl FileTypes {
+    /// Returns the file type with the given name, like *tar.gz*
+    pub fn from_name(name: &str) -> FileTypes {
+        match name {
+            "tar.xz" | "txz" => FileTypes::TarXz,
+            "tar.gz" | "tgz" => FileTypes::TarGz,
+            "tar.bz2" | "tbz2" => FileTypes::TarBz2,
+            "tar.zst" | "tzst" => FileTypes::TarZst,
+            "zip" => FileTypes::Zip,
+            "git" => FileTypes::Git,
+            "raw" | "binary" => FileTypes::Raw,
+            _ => FileTypes::Unknown,
+        }
+    }
+
+    /// Returns the name of the file type, under which its decoder is registered
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileTypes::TarXz => "tar.xz",
+            FileTypes::TarGz => "tar.gz",
+            FileTypes::TarBz2 => "tar.bz2",
+            FileTypes::TarZst => "tar.zst",
+            FileTypes::Zip => "zip",
+            FileTypes::Git => "git",
+            FileTypes::Raw => "raw",
+            FileTypes::Unknown => "unknown",
+        }
+    }
+
+    /// Detects the type of a file from the magic bytes at its beginning.
+    /// Compressed files are assumed to hold a tar archive,
+    /// and ELF and Mach-O files are single executables.
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::File::open`.
+    pub fn detect(path: &Path) -> Result<FileTypes, Box<dyn Error>> {
+        let mut magic = Vec::with_capacity(6);
+        File::open(path)?.take(6).read_to_end(&mut magic)?;
+
+        let file_type = if magic.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            FileTypes::TarXz
+        } else if magic.starts_with(&[0x1F, 0x8B]) {
+            FileTypes::TarGz
+        } else if magic.starts_with(b"BZh") {
+            FileTypes::TarBz2
+        } else if magic.starts_with(&[0x28, 0xB5, 0x2F, 0xFD]) {
+            FileTypes::TarZst
+        } else if magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06") {
+            FileTypes::Zip
+        } else if magic.starts_with(b"\x7FELF")
+            || magic.starts_with(&[0xCF, 0xFA, 0xED, 0xFE])
+            || magic.starts_with(&[0xFE, 0xED, 0xFA, 0xCF])
+        {
+            FileTypes::Raw
+        } else {
+            FileTypes::Unknown
+        };
+
+        Ok(file_type)
+    }
 }
 
-/// General description of how a decoder must behave
+/// General description of how a decoder must behave.
+/// Decoders are used as trait objects, so `new` is only
+/// available when the concrete type is known.
 pub trait Decoder {
-    fn new() -> Self;
+    fn new() -> Self
+    where
+        Self: Sized;
     fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>>;
 }
 
+/// Decoders that can be used to unpack the downloaded files,
+/// registered under the file type they decode, like *tar.gz*.
+/// The name of a type is also the extension of its files, so files whose
+/// type is unknown are decoded by the decoder of their extension.
+pub struct DecoderRegistry {
+    decoders: HashMap<String, Box<dyn Decoder>>,
+}
+
+impl DecoderRegistry {
+    /// Creates a new `DecoderRegistry` without any decoder
+    pub fn new() -> DecoderRegistry {
+        DecoderRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Registers the decoder for a file type, replacing the previous one
+    pub fn register(&mut self, file_type: &str, decoder: Box<dyn Decoder>) {
+        self.decoders.insert(file_type.to_lowercase(), decoder);
+    }
+
+    /// Returns the decoder registered for the file type, if any
+    pub fn get(&self, file_type: &str) -> Option<&dyn Decoder> {
+        self.decoders
+            .get(&file_type.to_lowercase())
+            .map(|decoder| decoder.as_ref())
+    }
+
+    /// Returns the decoder for the file with the given name, going by
+    /// the longest registered file type that its name ends with
+    pub fn for_file(&self, file_name: &str) -> Option<&dyn Decoder> {
+        let file_name = file_name.to_lowercase();
+        self.decoders
+            .iter()
+            .filter(|(file_type, _)| file_name.ends_with(&format!(".{}", file_type)))
+            .max_by_key(|(file_type, _)| file_type.len())
+            .map(|(_, decoder)| decoder.as_ref())
+    }
+}
+
+impl Default for DecoderRegistry {
+    /// Creates a new `DecoderRegistry` with a decoder for every supported archive type
+    fn default() -> DecoderRegistry {
+        let mut registry = DecoderRegistry::new();
+        registry.register("tar.xz", Box::new(TarXzDecoder::new()));
+        registry.register("txz", Box::new(TarXzDecoder::new()));
+        registry.register("tar.gz", Box::new(TarGzDecoder::new()));
+        registry.register("tgz", Box::new(TarGzDecoder::new()));
+        registry.register("tar.bz2", Box::new(TarBz2Decoder::new()));
+        registry.register("tbz2", Box::new(TarBz2Decoder::new()));
+        registry.register("tar.zst", Box::new(TarZstDecoder::new()));
+        registry.register("tzst", Box::new(TarZstDecoder::new()));
+        registry.register("zip", Box::new(ZipDecoder::new()));
+        registry
+    }
+}
+
 /// A decoder for tar.xz files
 pub struct TarXzDecoder {}
 /// A decoder for tar.gz files
 pub struct TarGzDecoder {}
+/// A decoder for tar.bz2 files
+pub struct TarBz2Decoder {}
+/// A decoder for tar.zst files
+pub struct TarZstDecoder {}
+/// A decoder for zip files
+pub struct ZipDecoder {}
 
 impl Decoder for TarXzDecoder {
     /// creates a new tar.xz decoder
@@ -44,21 +233,15 @@ impl Decoder for TarXzDecoder {
         TarXzDecoder {}
     }
 
-    /// Decodes a tar.xz file
+    /// Decodes a tar.xz file.
+    /// The archive is decompressed while it is being unpacked, so it never
+    /// has to fit in memory nor be written to disk in its decompressed form.
     /// # Observations
     /// - file_name must be the full path to the file
     fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
-        let xz_file = File::open(file_name)?;
-        let mut decompressed = XzDecoder::new(xz_file);
-        // create a buffer in which to store the xz decomp data
-        let mut buf: Vec<u8> = Vec::new();
-        decompressed.read_to_end(&mut buf)?;
-        // create a temp file and store the contents of the buffer in it
-        let mut tar_file = NamedTempFile::new()?;
-        tar_file.write_all(buf.as_mut_slice())?;
-        // reopen the temp file to have another handle, and extract the contents
-        let tar_file_2 = tar_file.reopen()?;
-        let mut archive = Archive::new(tar_file_2);
+        let xz_file = BufReader::new(File::open(file_name)?);
+        let tar = XzDecoder::new(xz_file);
+        let mut archive = Archive::new(tar);
         archive.unpack(unpack_dir)?;
 
         Ok(())
@@ -71,13 +254,50 @@ impl Decoder for TarGzDecoder {
         TarGzDecoder {}
     }
 
-    // TODO: this implementation is too slow, try to optimize it
     /// Decodes a tar.gz file.
+    /// The archive is read through a large buffer, and its small files are
+    /// written by several threads while the rest of it is being inflated.
+    /// Building with the `fast-inflate` feature switches to a faster inflate backend.
     /// # Observations
     /// - file_name must be the full path to the file
     fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
-        let gz_file = File::open(file_name)?;
+        let gz_file = BufReader::with_capacity(ARCHIVE_BUFFER_SIZE, File::open(file_name)?);
         let tar = GzDecoder::new(gz_file);
+        unpack_parallel(Archive::new(tar), unpack_dir)
+    }
+}
+
+impl Decoder for TarBz2Decoder {
+    /// creates a new tar.bz2 decoder
+    fn new() -> TarBz2Decoder {
+        TarBz2Decoder {}
+    }
+
+    /// Decodes a tar.bz2 file.
+    /// # Observations
+    /// - file_name must be the full path to the file
+    fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let bz2_file = File::open(file_name)?;
+        let tar = BzDecoder::new(bz2_file);
+        let mut archive = Archive::new(tar);
+        archive.unpack(unpack_dir)?;
+
+        Ok(())
+    }
+}
+
+impl Decoder for TarZstDecoder {
+    /// creates a new tar.zst decoder
+    fn new() -> TarZstDecoder {
+        TarZstDecoder {}
+    }
+
+    /// Decodes a tar.zst file.
+    /// # Observations
+    /// - file_name must be the full path to the file
+    fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+        let zst_file = File::open(file_name)?;
+        let tar = zstd::stream::read::Decoder::new(zst_file)?;
         let mut archive = Archive::new(tar);
         archive.unpack(unpack_dir)?;
 
@@ -85,60 +305,924 @@ impl Decoder for TarGzDecoder {
     }
 }
 
+impl Decoder for ZipDecoder {
+    /// creates a new zip decoder
+    fn new() -> ZipDecoder {
+        ZipDecoder {}
+    }
+
+    /// Decodes a zip file, keeping the permission bits (like the executable
+    /// flags) and symlinks stored in the external attributes of its entries.
+    /// Entries whose path would end up outside of the unpack dir are skipped,
+    /// and like with tar.gz files, symlinks are created last and can't point
+    /// outside of it.
+    /// # Observations
+    /// - file_name must be the full path to the file
+    fn decode(&self, file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+        // symlinks are stored as files holding their target
+        const S_IFMT: u32 = 0o170000;
+        const S_IFLNK: u32 = 0o120000;
+
+        let zip_file = File::open(file_name)?;
+        let mut archive = ZipArchive::new(zip_file)?;
+        fs::create_dir_all(unpack_dir)?;
+        let mut checked_dirs = HashSet::new();
+        let mut links = Vec::new(); // (path, target)
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let relative = match entry.enclosed_name().and_then(sanitize_entry_path) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            let path = unpack_dir.join(&relative);
+            let mode = entry.unix_mode();
+
+            if entry.is_dir() {
+                create_dirs_inside(unpack_dir, &relative, &mut checked_dirs)?;
+            } else if matches!(mode, Some(mode) if mode & S_IFMT == S_IFLNK) {
+                let mut target = String::new();
+                entry.read_to_string(&mut target)?;
+                links.retain(|(link, _)| *link != relative);
+                links.push((relative, PathBuf::from(target)));
+                continue;
+            } else {
+                if let Some(parent) = relative.parent() {
+                    create_dirs_inside(unpack_dir, parent, &mut checked_dirs)?;
+                }
+                // the file replaces any earlier link of the archive
+                links.retain(|(link, _)| *link != relative);
+                remove_symlink(&path)?;
+                let mut out = File::create(&path)?;
+                io::copy(&mut entry, &mut out)?;
+            }
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                if let Some(mode) = mode {
+                    fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o7777))?;
+                }
+            }
+        }
+
+        // links go last, so no file gets written through them
+        for (path, target) in links {
+            if !symlink_stays_inside(&path, &target) {
+                return Err(Box::new(UnsafeArchivePathError));
+            }
+            if let Some(parent) = path.parent() {
+                create_dirs_inside(unpack_dir, parent, &mut checked_dirs)?;
+            }
+            let link = unpack_dir.join(&path);
+            if fs::symlink_metadata(&link).is_ok() {
+                fs::remove_file(&link)?;
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &link)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&target, &link)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A regular file of an archive, waiting to be written by a writer thread
+struct PendingFile {
+    path: PathBuf,
+    contents: Vec<u8>,
+    mode: u32,
+    mtime: u64,
+}
+
+/// Paths that are being written by the writer threads. A later entry of the
+/// archive for one of them waits for the write to end, so the last entry wins
+#[derive(Default)]
+struct PendingPaths {
+    paths: Mutex<HashSet<PathBuf>>,
+    written: Condvar,
+}
+
+impl PendingPaths {
+    /// Waits until the path is no longer being written, claiming it when `claim` is set
+    fn wait_for(&self, path: &Path, claim: bool) {
+        let mut paths = self.paths.lock().unwrap();
+        while paths.contains(path) {
+            paths = self.written.wait(paths).unwrap();
+        }
+        if claim {
+            paths.insert(path.to_path_buf());
+        }
+    }
+
+    /// Lets the ones waiting for the path know that it has been written
+    fn release(&self, path: &Path) {
+        self.paths.lock().unwrap().remove(path);
+        self.written.notify_all();
+    }
+}
+
+/// Unpacks a tar archive into the given directory, handing its small files
+/// to a pool of writer threads so that decompression never waits on the disk.
+/// Like `tar::Archive::unpack`, entries can't end up outside of the unpack dir,
+/// the last entry of a path wins, permissions and modification times are kept,
+/// and links are created last, hard links before symlinks.
+///
+/// # Errors
+/// The function will return an error if the archive is corrupted, if an entry
+/// would be written through a symlink, if a link points outside of the unpack dir,
+/// or if any of the files can't be written.
+fn unpack_parallel<R: Read>(
+    mut archive: Archive<R>,
+    unpack_dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(unpack_dir)?;
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(8);
+    let (sender, receiver) = mpsc::sync_channel::<PendingFile>(workers * 8);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let pending = Arc::new(PendingPaths::default());
+    let handles: Vec<thread::JoinHandle<io::Result<()>>> = (0..workers)
+        .map(|_| {
+            let receiver = Arc::clone(&receiver);
+            let pending = Arc::clone(&pending);
+            thread::spawn(move || loop {
+                let file = match receiver.lock().unwrap().recv() {
+                    Ok(file) => file,
+                    Err(_) => return Ok(()),
+                };
+                let written = fs::write(&file.path, &file.contents)
+                    .and_then(|_| finish_file(&file.path, file.mode, file.mtime));
+                pending.release(&file.path);
+                written?;
+            })
+        })
+        .collect();
+
+    let mut checked_dirs = HashSet::new();
+    let mut links = Vec::new(); // (path, target, is_symlink)
+    let mut dirs = Vec::new(); // (path, mode)
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = match sanitize_entry_path(&entry.path()?) {
+                Some(path) => path,
+                None => continue,
+            };
+            let header = entry.header();
+            let mode = header.mode().unwrap_or(0o644);
+            let mtime = header.mtime().unwrap_or(0);
+            match header.entry_type() {
+                EntryType::Directory => {
+                    create_dirs_inside(unpack_dir, &path, &mut checked_dirs)?;
+                    dirs.push((unpack_dir.join(&path), mode));
+                }
+                EntryType::Regular | EntryType::Continuous => {
+                    if let Some(parent) = path.parent() {
+                        create_dirs_inside(unpack_dir, parent, &mut checked_dirs)?;
+                    }
+                    // the file replaces any earlier link of the archive
+                    links.retain(|(link, _, _)| *link != path);
+                    let path = unpack_dir.join(&path);
+                    let in_parallel = entry.size() <= PARALLEL_WRITE_LIMIT;
+                    pending.wait_for(&path, in_parallel);
+                    remove_symlink(&path)?;
+                    if in_parallel {
+                        let mut contents = Vec::with_capacity(entry.size() as usize);
+                        entry.read_to_end(&mut contents)?;
+                        let file = PendingFile {
+                            path,
+                            contents,
+                            mode,
+                            mtime,
+                        };
+                        // the writers only hang up when one of them failed
+                        if sender.send(file).is_err() {
+                            break;
+                        }
+                    } else {
+                        io::copy(&mut entry, &mut File::create(&path)?)?;
+                        finish_file(&path, mode, mtime)?;
+                    }
+                }
+                EntryType::Symlink | EntryType::Link => {
+                    if let Some(target) = entry.link_name()? {
+                        let is_symlink = header.entry_type() == EntryType::Symlink;
+                        links.retain(|(link, _, _)| *link != path);
+                        links.push((path, target.into_owned(), is_symlink));
+                    }
+                }
+                // devices, fifos and the like are not needed to install anything
+                _ => continue,
+            }
+        }
+
+        Ok(())
+    })();
+
+    drop(sender);
+    let mut write_result = Ok(());
+    for handle in handles {
+        match handle.join() {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => write_result = Err(e),
+            Err(_) => write_result = Err(io::Error::other("A writer thread panicked")),
+        }
+    }
+    result?;
+    write_result?;
+
+    // links go last, so no file gets written through them, and hard links
+    // go before symlinks, so none of them is resolved through a symlink
+    links.sort_by_key(|(_, _, is_symlink)| *is_symlink);
+    let root = fs::canonicalize(unpack_dir)?;
+    for (path, target, is_symlink) in links {
+        if let Some(parent) = path.parent() {
+            create_dirs_inside(unpack_dir, parent, &mut checked_dirs)?;
+        }
+        let link = unpack_dir.join(&path);
+        if fs::symlink_metadata(&link).is_ok() {
+            fs::remove_file(&link)?;
+        }
+        if is_symlink {
+            if !symlink_stays_inside(&path, &target) {
+                return Err(Box::new(UnsafeArchivePathError));
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &link)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&target, &link)?;
+        } else {
+            let target = match sanitize_entry_path(&target) {
+                Some(target) => unpack_dir.join(target),
+                None => return Err(Box::new(UnsafeArchivePathError)),
+            };
+            // the unpack dir may hold symlinks from before the unpacking
+            if !fs::canonicalize(&target)?.starts_with(&root) {
+                return Err(Box::new(UnsafeArchivePathError));
+            }
+            fs::hard_link(target, &link)?;
+        }
+    }
+    // directories may not be writable, so their permissions go at the very end
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        dirs.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+        for (path, mode) in dirs {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the path of an archive entry without its leading `./`,
+/// or `None` when it is absolute or goes up the directory tree
+fn sanitize_entry_path(path: &Path) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir => continue,
+            _ => return None,
+        }
+    }
+    if sanitized.as_os_str().is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Checks that the target of a symlink, which is relative to the directory
+/// of the symlink, doesn't point outside of the unpack dir
+fn symlink_stays_inside(link: &Path, target: &Path) -> bool {
+    let mut depth = match link.parent() {
+        Some(parent) => parent.components().count(),
+        None => 0,
+    };
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 => depth -= 1,
+            // going above the unpack dir, or an absolute target
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Creates the directories of a relative path inside of the unpack dir,
+/// refusing to go through symlinks that might point somewhere else
+fn create_dirs_inside(
+    unpack_dir: &Path,
+    relative: &Path,
+    checked: &mut HashSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut dir = unpack_dir.to_path_buf();
+    for component in relative.components() {
+        dir.push(component);
+        if checked.contains(&dir) {
+            continue;
+        }
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(Box::new(UnsafeArchivePathError))
+            }
+            Ok(_) => (),
+            Err(_) => fs::create_dir(&dir)?,
+        }
+        checked.insert(dir.clone());
+    }
+
+    Ok(())
+}
+
+/// Removes a symlink left in the way of a file, so the file isn't written through it
+fn remove_symlink(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// Gives a freshly written file the permissions and modification time it had in the archive
+fn finish_file(path: &Path, mode: u32, mtime: u64) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    filetime::set_file_mtime(path, FileTime::from_unix_time(mtime as i64, 0))
+}
+
+/// A step of a scripted installation: the command to run, with its
+/// arguments, working dir (relative to the source dir) and environment.
+/// *$PREFIX*, *$SRC_DIR* and *$VERSION* are replaced in every one of them.
+pub struct InstallStep {
+    pub command: String,
+    pub args: Vec<String>,
+    pub dir: Option<String>,
+    pub env: Vec<(String, String)>,
+}
+
+/// A named build option declared by a package, like *ssl*, along with
+/// the flags passed to the build when it is enabled or disabled
+pub struct BuildOption {
+    pub name: String,
+    pub default: bool,
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+}
+
+/// The build options that the user explicitly enabled or disabled,
+/// the rest of them keep their defaults
+#[derive(Clone, Default)]
+pub struct Variant {
+    pub with: Vec<String>,
+    pub without: Vec<String>,
+}
+
+impl Variant {
+    /// Returns the flags that the variant picks from the given options
+    ///
+    /// # Errors
+    /// The function will return an error if the variant has an option that is not
+    /// among the given ones. Check out the documentation for UnknownBuildOptionError.
+    pub fn flags(&self, options: &[BuildOption]) -> Result<Vec<String>, Box<dyn Error>> {
+        for name in self.with.iter().chain(self.without.iter()) {
+            if !options.iter().any(|option| option.name == *name) {
+                return Err(Box::new(UnknownBuildOptionError {
+                    name: name.clone(),
+                    available: options.iter().map(|option| option.name.clone()).collect(),
+                }));
+            }
+        }
+
+        let mut flags = Vec::new();
+        for option in options {
+            let enabled = if self.with.contains(&option.name) {
+                true
+            } else if self.without.contains(&option.name) {
+                false
+            } else {
+                option.default
+            };
+            if enabled {
+                flags.extend(option.enabled.iter().cloned());
+            } else {
+                flags.extend(option.disabled.iter().cloned());
+            }
+        }
+
+        Ok(flags)
+    }
+
+    /// Returns a copy of the variant without the options that are not among the given ones
+    pub fn restricted_to(&self, options: &[BuildOption]) -> Variant {
+        let is_known = |name: &&String| options.iter().any(|option| option.name == **name);
+        Variant {
+            with: self.with.iter().filter(is_known).cloned().collect(),
+            without: self.without.iter().filter(is_known).cloned().collect(),
+        }
+    }
+}
+
+/// Replaces the known variables of a string, written as *$NAME* or *${NAME}*.
+/// Unknown variables are left untouched.
+fn expand_variables(str: &str, variables: &[(&str, String)]) -> String {
+    let mut expanded = String::with_capacity(str.len());
+    let mut rest = str;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        match variables.iter().find(|(variable, _)| *variable == name) {
+            Some((_, value)) if len > 0 => {
+                expanded.push_str(value);
+                rest = &after[len..];
+            }
+            _ => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
 /// Command runner that abstracts the interaction with third-party
 /// programs that aid in the installation process, like *make*, *git*, etc
 pub enum CommandRunner<'a> {
-    Make(&'a str, &'a str), // args: install_target, sage_home_path
-    Bin(&'a str, &'a str),  // args: install_target, sage_home_path
-                            // Git, // not implemented yet // TODO: Add other commands to the command runner, like git.
+    // args: source_dir, install_dir, build_log, flags of the build options
+    Make(&'a Path, &'a Path, &'a BuildLog, &'a [String]),
+    CMake(&'a Path, &'a Path, &'a BuildLog, &'a [String]),
+    Meson(&'a Path, &'a Path, &'a BuildLog, &'a [String]),
+    Cargo(&'a Path, &'a Path, &'a BuildLog, &'a [String]),
+    // args: source_dir, install_dir, build_log, steps, version
+    Script(&'a Path, &'a Path, &'a BuildLog, &'a [InstallStep], &'a str),
+    Bin(&'a Path, &'a Path),         // args: source_dir, install_dir
+    Git(&'a str, &'a Path, &'a str), // args: url, clone_dir, revision
 }
 
 impl<'a> CommandRunner<'a> {
     /// Run the specified command
     pub fn run(&self) -> Result<(), Box<dyn Error>> {
         match self {
-            // If the specified command is make
-            Self::Make(program, sage_home_path) => {
-                // create the ./configure command
-                let mut configure = Command::new("./configure");
-                configure
-                    .current_dir(format!("{}/downloads/{}", sage_home_path, program))
-                    .arg(format!("--prefix={}/programs/{}", sage_home_path, program))
-                    .arg(format!(
-                        "--exec-prefix={}/programs/{}",
-                        sage_home_path, program
-                    ))
-                    // run it
-                    .output()?;
-                // now create the make command
-                let mut make = Command::new("make");
-                make.current_dir(format!("{}/downloads/{}", sage_home_path, program));
-                // run make
-                make.output()?;
-                // now run make install
-                make.arg("install").output()?;
+            // If the specified command is a build from source
+            Self::Make(_, _, build_log, _)
+            | Self::CMake(_, _, build_log, _)
+            | Self::Meson(_, _, build_log, _)
+            | Self::Cargo(_, _, build_log, _)
+            | Self::Script(_, _, build_log, _, _) => {
+                // run every step, stopping at the first one that fails
+                for (step, mut command) in self.build_steps() {
+                    build_log.run_step(&step, &mut command)?;
+                }
 
                 Ok(())
             }
             // If the specified command is a binary copy
-            Self::Bin(install_target, sage_home_path) => {
-                // Copy everything from the install_target dir
-                // to the sage_home_path directory
+            Self::Bin(source_dir, install_dir) => {
+                // Copy everything from the source dir
+                // to the install directory
                 let mut copy_options = fs_extra::dir::CopyOptions::new();
                 copy_options.overwrite = true;
-                // copy_options.copy_inside = true;
-                let mut from_paths = Vec::new();
-                // copy everything inside the install target dir
-                from_paths.push(format!("{}/downloads/{}", sage_home_path, install_target));
-                // path to which the files will be copied to
-                let copy_target_path = format!("{}/programs", sage_home_path);
-                // copy them
-                fs_extra::copy_items(&from_paths, &copy_target_path, &copy_options)?;
+                // the install dir doesn't exist yet, so the contents
+                // of the source dir are copied straight into it
+                copy_options.copy_inside = true;
+                fs_extra::dir::copy(source_dir, install_dir, &copy_options)?;
+
+                Ok(())
+            }
+            // If the specified command is a git clone
+            Self::Git(url, clone_dir, revision) => {
+                // a revision like --upload-pack=... would be taken as an option
+                if revision.starts_with('-') {
+                    return Err(Box::new(GitCommandError {
+                        command: format!("checkout {}", revision),
+                        stderr: String::from("not a valid revision"),
+                    }));
+                }
+                // always start from a fresh clone
+                if clone_dir.exists() {
+                    fs::remove_dir_all(clone_dir)?;
+                }
+                let clone_dir_str = clone_dir.to_str().unwrap();
+                git(
+                    None,
+                    &[
+                        "clone",
+                        "--quiet",
+                        "--no-checkout",
+                        "--",
+                        url,
+                        clone_dir_str,
+                    ],
+                )?;
+                let checkout = ["checkout", "--quiet", "--detach", revision];
+                git(Some(clone_dir), &checkout)?;
 
                 Ok(())
             }
         }
     }
+
+    /// Returns the steps of a build from source, each one with its name.
+    /// Every build system is pointed at the install directory as its prefix,
+    /// and gets the flags of the build options in its configure step.
+    fn build_steps(&self) -> Vec<(String, Command)> {
+        // creates a named step that runs inside of the source dir
+        let step = |name: &str, source_dir: &Path, program: &str, args: &[&str]| {
+            let mut command = Command::new(program);
+            command.current_dir(source_dir).args(args);
+            (name.to_string(), command)
+        };
+        // adds the flags of the build options to a step
+        let with_flags = |(name, mut command): (String, Command), flags: &[String]| {
+            command.args(flags);
+            (name, command)
+        };
+        match self {
+            Self::Make(src, install_dir, _, flags) => {
+                let prefix = format!("--prefix={}", install_dir.display());
+                let exec_prefix = format!("--exec-prefix={}", install_dir.display());
+                let configure = step("configure", src, "./configure", &[&prefix, &exec_prefix]);
+                vec![
+                    with_flags(configure, flags),
+                    step("make", src, "make", &[]),
+                    step("make install", src, "make", &["install"]),
+                ]
+            }
+            Self::CMake(src, install_dir, _, flags) => {
+                let prefix = format!("-DCMAKE_INSTALL_PREFIX={}", install_dir.display());
+                let release = "-DCMAKE_BUILD_TYPE=Release";
+                let configure = step(
+                    "cmake",
+                    src,
+                    "cmake",
+                    &["-S", ".", "-B", "build", &prefix, release],
+                );
+                vec![
+                    with_flags(configure, flags),
+                    step("cmake build", src, "cmake", &["--build", "build"]),
+                    step("cmake install", src, "cmake", &["--install", "build"]),
+                ]
+            }
+            Self::Meson(src, install_dir, _, flags) => {
+                let prefix = format!("--prefix={}", install_dir.display());
+                let release = "--buildtype=release";
+                let setup = step(
+                    "meson setup",
+                    src,
+                    "meson",
+                    &["setup", "build", &prefix, release],
+                );
+                vec![
+                    with_flags(setup, flags),
+                    step("meson compile", src, "meson", &["compile", "-C", "build"]),
+                    step("meson install", src, "meson", &["install", "-C", "build"]),
+                ]
+            }
+            Self::Cargo(src, install_dir, _, flags) => {
+                let root = install_dir.display().to_string();
+                let mut args = vec!["install", "--path", ".", "--root", &root];
+                // build with the exact dependencies the package was released with
+                if src.join("Cargo.lock").is_file() {
+                    args.push("--locked");
+                }
+                vec![with_flags(
+                    step("cargo install", src, "cargo", &args),
+                    flags,
+                )]
+            }
+            Self::Script(source_dir, install_dir, _, steps, version) => {
+                let variables = [
+                    ("PREFIX", install_dir.display().to_string()),
+                    ("SRC_DIR", source_dir.display().to_string()),
+                    ("VERSION", version.to_string()),
+                ];
+                steps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, step)| {
+                        let program = expand_variables(&step.command, &variables);
+                        let mut command = Command::new(&program);
+                        // relative working dirs are relative to the source dir
+                        let dir = match &step.dir {
+                            Some(dir) => source_dir.join(expand_variables(dir, &variables)),
+                            None => source_dir.to_path_buf(),
+                        };
+                        command.current_dir(dir);
+                        for arg in &step.args {
+                            command.arg(expand_variables(arg, &variables));
+                        }
+                        for (name, value) in &variables {
+                            command.env(name, value);
+                        }
+                        for (name, value) in &step.env {
+                            command.env(name, expand_variables(value, &variables));
+                        }
+                        (format!("step {} ({})", i + 1, program), command)
+                    })
+                    .collect()
+            }
+            Self::Bin(_, _) | Self::Git(_, _, _) => Vec::new(),
+        }
+    }
+}
+
+/// Returns the commit that the repository in the given dir is at
+///
+/// # Errors
+/// Check out the documentation for `git`.
+pub fn head_commit(repo_dir: &Path) -> Result<String, Box<dyn Error>> {
+    git(Some(repo_dir), &["rev-parse", "HEAD"])
+}
+
+/// Runs git, inside of the given repository if any, returning what it printed
+///
+/// # Errors
+/// The function will return an error if git can't be run or if it fails.
+fn git(repo_dir: Option<&Path>, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut command = Command::new("git");
+    if let Some(repo_dir) = repo_dir {
+        command.arg("-C").arg(repo_dir);
+    }
+    // detached checkouts are expected, there is no need to warn about them
+    let output = command
+        .args(["-c", "advice.detachedHead=false"])
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::new(GitCommandError {
+            command: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Log file that collects everything printed by the steps of a build,
+/// which can also be mirrored to the terminal as the steps run
+pub struct BuildLog {
+    path: PathBuf,
+    verbose: bool,
+}
+
+impl BuildLog {
+    /// Creates a new `BuildLog` that appends to the file at the given path
+    pub fn new(path: &Path, verbose: bool) -> BuildLog {
+        BuildLog {
+            path: path.to_path_buf(),
+            verbose,
+        }
+    }
+
+    /// Returns true if the output of the build is mirrored to the terminal
+    pub fn is_verbose(&self) -> bool {
+        self.verbose
+    }
+
+    /// Runs a step of the build, appending what it prints to the log.
+    ///
+    /// # Errors
+    /// The function will return a `BuildError` with the end of the log if the
+    /// command can't be started or doesn't exit successfully.
+    /// Check out the documentation for `std::fs::OpenOptions::open` to find out
+    /// other reasons for this function to fail.
+    pub fn run_step(&self, step: &str, command: &mut Command) -> Result<(), Box<dyn Error>> {
+        self.run_step_with(step, command, &mut io::stdout(), &mut io::stderr())
+    }
+
+    /// Runs a step of the build like `run_step`, mirroring what it prints
+    /// to the given writers instead of the terminal
+    fn run_step_with<O: Write, E: Write + Send>(
+        &self,
+        step: &str,
+        command: &mut Command,
+        stdout: &mut O,
+        stderr: &mut E,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(log, "==> {}", step)?;
+
+        command.stdin(Stdio::null());
+        let status = if self.verbose {
+            run_mirrored(command, log.try_clone()?, stdout, stderr)
+        } else {
+            command
+                .stdout(log.try_clone()?)
+                .stderr(log.try_clone()?)
+                .status()
+        };
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(self.failure(step, status.code())),
+            Err(e) => {
+                writeln!(log, "{} could not be started: {}", step, e)?;
+                Err(self.failure(step, None))
+            }
+        }
+    }
+
+    /// Builds the error for a failed step out of the end of the log
+    fn failure(&self, step: &str, status: Option<i32>) -> Box<dyn Error> {
+        let contents = fs::read(&self.path).unwrap_or_default();
+        let contents = String::from_utf8_lossy(&contents);
+        let lines: Vec<&str> = contents.lines().collect();
+        let tail = lines[lines.len().saturating_sub(LOG_TAIL_LINES)..]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        Box::new(BuildError {
+            step: step.to_string(),
+            status,
+            log: self.path.clone(),
+            tail,
+        })
+    }
+}
+
+/// Runs a command, copying what it prints both to the log and to the given
+/// writers, which are the ones of the terminal outside of the tests
+fn run_mirrored<O: Write, E: Write + Send>(
+    command: &mut Command,
+    log: File,
+    stdout: &mut O,
+    stderr: &mut E,
+) -> io::Result<ExitStatus> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let log = Mutex::new(log);
+    let child_stdout = child.stdout.take().unwrap();
+    let child_stderr = child.stderr.take().unwrap();
+    let (stdout_result, stderr_result) = thread::scope(|scope| {
+        let stderr_thread = scope.spawn(|| mirror(child_stderr, &log, stderr));
+        let stdout_result = mirror(child_stdout, &log, stdout);
+        let stderr_result = stderr_thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("The stderr reader panicked")));
+        (stdout_result, stderr_result)
+    });
+    let status = child.wait()?;
+    stdout_result?;
+    stderr_result?;
+
+    Ok(status)
+}
+
+/// Copies the output of a command line by line, so the lines of
+/// stdout and stderr don't get mixed up in the log
+fn mirror<R: Read, W: Write>(output: R, log: &Mutex<File>, terminal: &mut W) -> io::Result<()> {
+    for line in BufReader::new(output).split(b'\n') {
+        let mut line = line?;
+        line.push(b'\n');
+        log.lock().unwrap().write_all(&line)?;
+        terminal.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+/// Installs a single executable as *bin/<name>* inside of the install directory,
+/// making it executable. The name can't be a path, so the executable always
+/// ends up in the *bin* directory.
+///
+/// # Errors
+/// The function will return an error if the name is not a plain file name.
+/// Check out the documentation for `std::fs::copy` and `std::fs::set_permissions`
+/// to find out other reasons for this function to fail.
+pub fn install_binary(file: &Path, install_dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    if !is_plain_name(name) {
+        return Err(Box::new(InvalidBinaryNameError));
+    }
+    let bin_dir = install_dir.join("bin");
+    fs::create_dir_all(&bin_dir)?;
+    let target = bin_dir.join(name);
+    fs::copy(file, &target)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that a name is a plain file name, which always stays inside of
+/// the directory it is joined to, unlike *..* or *bin/tool*
+pub fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    let is_single = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    );
+
+    is_single && !name.contains(['/', '\\'])
+}
+
+/// Returns the directory in which a version of a package is installed,
+/// like *programs/python/3.8.0*
+pub fn version_dir(install_dir: &Path, name: &str, version: &str) -> PathBuf {
+    install_dir.join(name).join(version)
+}
+
+/// Returns the pointer to the active version of a package,
+/// like *programs/python/current*
+fn active_pointer(install_dir: &Path, name: &str) -> PathBuf {
+    install_dir.join(name).join("current")
+}
+
+/// Returns the active version of a package, if any
+pub fn active_version(install_dir: &Path, name: &str) -> Option<String> {
+    let target = fs::read_link(active_pointer(install_dir, name)).ok()?;
+    target.to_str().map(String::from)
+}
+
+/// Makes the specified version the active one of the package,
+/// by flipping its *current* pointer
+pub fn activate_version(
+    install_dir: &Path,
+    name: &str,
+    version: &str,
+) -> Result<(), Box<dyn Error>> {
+    let pointer = active_pointer(install_dir, name);
+    // create the new pointer next to the old one and swap them,
+    // so that there is always an active version
+    let tmp_pointer = pointer.with_extension("tmp");
+    if fs::symlink_metadata(&tmp_pointer).is_ok() {
+        fs::remove_file(&tmp_pointer)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(version, &tmp_pointer)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_dir(version, &tmp_pointer)?;
+    fs::rename(&tmp_pointer, &pointer)?;
+
+    Ok(())
+}
+
+/// Leaves the package without an active version
+pub fn deactivate_version(install_dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    let pointer = active_pointer(install_dir, name);
+    if fs::symlink_metadata(&pointer).is_ok() {
+        fs::remove_file(&pointer)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the paths of every file (and symlink) found under `dir`,
+/// walking through its subdirectories recursively
+pub fn walk_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // don't follow symlinks, they are files on their own
+        if entry.file_type()?.is_dir() {
+            files.append(&mut walk_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
 }
 
 // #[cfg(test)]
@@ -163,3 +1247,608 @@ impl<'a> CommandRunner<'a> {
 //         decoder.decode(file, &path).unwrap();
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a tar archive holding a single file
+    fn tar_with_file(path: &str, contents: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, contents).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn tar_xz_is_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = tar_with_file("tool-1.0/README", b"docs");
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar).unwrap();
+        fs::write(dir.path().join("tool.tar.xz"), xz.finish().unwrap()).unwrap();
+
+        let unpack_dir = dir.path().join("xz");
+        TarXzDecoder::new()
+            .decode(
+                dir.path().join("tool.tar.xz").to_str().unwrap(),
+                &unpack_dir,
+            )
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(unpack_dir.join("tool-1.0/README")).unwrap(),
+            "docs"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tar_gz_is_unpacked_in_parallel() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: &str, contents: &[u8], mode: u32| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(mode);
+            header.set_mtime(1_500_000_000);
+            header.set_cksum();
+            builder.append_data(&mut header, path, contents).unwrap();
+        };
+        for i in 0..200 {
+            append(&format!("tool-1.0/share/{}.txt", i), b"small", 0o644);
+        }
+        append("tool-1.0/bin/tool", b"#!/bin/sh\n", 0o755);
+        let big = vec![b'x'; PARALLEL_WRITE_LIMIT as usize + 1];
+        append("tool-1.0/lib/libtool.a", &big, 0o644);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_cksum();
+        builder
+            .append_link(&mut header, "tool-1.0/bin/t", "tool")
+            .unwrap();
+        // tar refuses to build paths that go up, so write the name by hand
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(0);
+        header.set_cksum();
+        builder.append(&header, &[][..]).unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gz.write_all(&tar).unwrap();
+        fs::write(dir.path().join("tool.tar.gz"), gz.finish().unwrap()).unwrap();
+
+        let unpack_dir = dir.path().join("unpacked");
+        TarGzDecoder::new()
+            .decode(
+                dir.path().join("tool.tar.gz").to_str().unwrap(),
+                &unpack_dir,
+            )
+            .unwrap();
+
+        let root = unpack_dir.join("tool-1.0");
+        assert_eq!(fs::read_dir(root.join("share")).unwrap().count(), 200);
+        assert_eq!(
+            fs::read_to_string(root.join("share/7.txt")).unwrap(),
+            "small"
+        );
+        assert_eq!(
+            fs::metadata(root.join("lib/libtool.a")).unwrap().len(),
+            big.len() as u64
+        );
+        let metadata = fs::metadata(root.join("bin/tool")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        assert_eq!(
+            FileTime::from_last_modification_time(&metadata).unix_seconds(),
+            1_500_000_000
+        );
+        assert_eq!(
+            fs::read_link(root.join("bin/t")).unwrap(),
+            PathBuf::from("tool")
+        );
+        assert!(!dir.path().join("escape").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn duplicate_entries_are_unpacked_in_order() {
+        let big = |byte: u8| vec![byte; PARALLEL_WRITE_LIMIT as usize + 1];
+        let append = |builder: &mut tar::Builder<Vec<u8>>, path: &str, contents: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, contents).unwrap();
+        };
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, "tool/a", b"first");
+        append(&mut builder, "tool/a", &big(b'x'));
+        append(&mut builder, "tool/a", b"last");
+        append(&mut builder, "tool/b", b"small");
+        append(&mut builder, "tool/b", &big(b'y'));
+        for i in 0..50 {
+            append(&mut builder, "tool/c", format!("version {}", i).as_bytes());
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_cksum();
+        builder.append_link(&mut header, "tool/d", "a").unwrap();
+        append(&mut builder, "tool/d", b"replaced the link");
+        let tar = builder.into_inner().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        unpack_parallel(Archive::new(&tar[..]), dir.path()).unwrap();
+        let read = |file: &str| fs::read(dir.path().join("tool").join(file)).unwrap();
+        assert_eq!(read("a"), b"last");
+        assert_eq!(read("b"), big(b'y'));
+        assert_eq!(read("c"), b"version 49");
+        assert_eq!(read("d"), b"replaced the link");
+        assert!(!fs::symlink_metadata(dir.path().join("tool/d"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        // links can't point outside of the unpack dir
+        let escaping = |target: &str| {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            header.set_cksum();
+            builder.append_link(&mut header, "tool/up", target).unwrap();
+            let tar = builder.into_inner().unwrap();
+            unpack_parallel(Archive::new(&tar[..]), &dir.path().join("escaping"))
+        };
+        assert!(escaping("../../outside").is_err());
+        assert!(escaping("/etc/passwd").is_err());
+        assert!(escaping("../tool/a").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_cant_write_through_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let unpack_dir = dir.path().join("unpacked");
+        fs::create_dir(&unpack_dir).unwrap();
+        std::os::unix::fs::symlink(&outside, unpack_dir.join("lib")).unwrap();
+
+        let mut checked = HashSet::new();
+        let result = create_dirs_inside(&unpack_dir, Path::new("lib/sub"), &mut checked);
+        assert!(result.is_err());
+        assert!(!outside.join("sub").exists());
+        assert_eq!(sanitize_entry_path(Path::new("/etc/passwd")), None);
+        assert_eq!(
+            sanitize_entry_path(Path::new("./tool/bin")),
+            Some(PathBuf::from("tool/bin"))
+        );
+    }
+
+    #[test]
+    fn tar_bz2_and_zst_are_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let tar = tar_with_file("tool-1.0/README", b"docs");
+
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::Default);
+        bz2.write_all(&tar).unwrap();
+        fs::write(dir.path().join("tool.tar.bz2"), bz2.finish().unwrap()).unwrap();
+        let zst = zstd::stream::encode_all(&tar[..], 3).unwrap();
+        fs::write(dir.path().join("tool.tar.zst"), zst).unwrap();
+
+        let bz2_dir = dir.path().join("bz2");
+        TarBz2Decoder::new()
+            .decode(dir.path().join("tool.tar.bz2").to_str().unwrap(), &bz2_dir)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(bz2_dir.join("tool-1.0/README")).unwrap(),
+            "docs"
+        );
+        let zst_dir = dir.path().join("zst");
+        TarZstDecoder::new()
+            .decode(dir.path().join("tool.tar.zst").to_str().unwrap(), &zst_dir)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(zst_dir.join("tool-1.0/README")).unwrap(),
+            "docs"
+        );
+
+        let detect = |file: &str| FileTypes::detect(&dir.path().join(file)).unwrap();
+        assert!(matches!(detect("tool.tar.bz2"), FileTypes::TarBz2));
+        assert!(matches!(detect("tool.tar.zst"), FileTypes::TarZst));
+    }
+
+    #[test]
+    fn file_types_are_detected_from_magic_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let detect = |contents: &[u8]| {
+            let path = dir.path().join("download");
+            fs::write(&path, contents).unwrap();
+            FileTypes::detect(&path).unwrap()
+        };
+
+        assert!(matches!(
+            detect(&[0xFD, b'7', b'z', b'X', b'Z', 0x00, 0x00]),
+            FileTypes::TarXz
+        ));
+        assert!(matches!(detect(&[0x1F, 0x8B, 0x08]), FileTypes::TarGz));
+        assert!(matches!(detect(b"PK\x03\x04rest"), FileTypes::Zip));
+        assert!(matches!(detect(b"#!/bin/sh"), FileTypes::Unknown));
+        assert!(matches!(detect(b"\x7FELF\x02\x01"), FileTypes::Raw));
+        assert!(matches!(detect(b""), FileTypes::Unknown));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_decoder_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("tool-1.0.zip");
+        let mut writer = ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .add_directory("tool-1.0/bin/", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file(
+                "tool-1.0/bin/tool",
+                FileOptions::default().unix_permissions(0o755),
+            )
+            .unwrap();
+        writer.write_all(b"#!/bin/sh\n").unwrap();
+        writer
+            .start_file(
+                "tool-1.0/README",
+                FileOptions::default().unix_permissions(0o644),
+            )
+            .unwrap();
+        writer.write_all(b"docs").unwrap();
+        writer
+            .start_file("../escape", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file(
+                "tool-1.0/bin/t",
+                FileOptions::default().unix_permissions(0o777),
+            )
+            .unwrap();
+        writer.write_all(b"tool").unwrap();
+        writer.finish().unwrap();
+        // the zip writer can't store symlinks, so the entries written
+        // with 0o777 are turned into symlinks afterwards
+        let as_symlinks = |zip_path: &Path| {
+            let file = (0o100777u32 << 16).to_le_bytes();
+            let symlink = (0o120777u32 << 16).to_le_bytes();
+            let mut bytes = fs::read(zip_path).unwrap();
+            while let Some(i) = bytes.windows(4).position(|window| window == file) {
+                bytes[i..i + 4].copy_from_slice(&symlink);
+            }
+            fs::write(zip_path, bytes).unwrap();
+        };
+        as_symlinks(&zip_path);
+
+        let unpack_dir = dir.path().join("unpacked");
+        fs::create_dir(&unpack_dir).unwrap();
+        ZipDecoder::new()
+            .decode(zip_path.to_str().unwrap(), &unpack_dir)
+            .unwrap();
+
+        let mode = |path: &str| {
+            let metadata = fs::metadata(unpack_dir.join(path)).unwrap();
+            metadata.permissions().mode() & 0o777
+        };
+        assert_eq!(mode("tool-1.0/bin/tool"), 0o755);
+        assert_eq!(mode("tool-1.0/README"), 0o644);
+        assert!(!dir.path().join("escape").exists());
+        assert_eq!(
+            fs::read_link(unpack_dir.join("tool-1.0/bin/t")).unwrap(),
+            Path::new("tool")
+        );
+
+        // nothing gets written through a symlink that points outside of the unpack dir
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        let evil_path = dir.path().join("evil.zip");
+        let mut writer = ZipWriter::new(File::create(&evil_path).unwrap());
+        writer
+            .start_file("tmp", FileOptions::default().unix_permissions(0o777))
+            .unwrap();
+        writer
+            .write_all(outside.to_str().unwrap().as_bytes())
+            .unwrap();
+        writer
+            .start_file("tmp/evil", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"evil").unwrap();
+        writer.finish().unwrap();
+        as_symlinks(&evil_path);
+
+        let unpack_dir = dir.path().join("evil");
+        let err = ZipDecoder::new()
+            .decode(evil_path.to_str().unwrap(), &unpack_dir)
+            .err()
+            .unwrap();
+        assert!(err.is::<UnsafeArchivePathError>());
+        assert!(!outside.join("evil").exists());
+    }
+
+    #[test]
+    fn decoders_are_looked_up_by_type_and_extension() {
+        struct FakeDecoder;
+
+        impl Decoder for FakeDecoder {
+            fn new() -> FakeDecoder {
+                FakeDecoder
+            }
+
+            fn decode(&self, _file_name: &str, unpack_dir: &Path) -> Result<(), Box<dyn Error>> {
+                fs::write(unpack_dir.join("decoded"), "fake")?;
+                Ok(())
+            }
+        }
+
+        let mut registry = DecoderRegistry::default();
+        assert!(registry.get(FileTypes::TarZst.name()).is_some());
+        assert!(registry.get("tar.lz").is_none());
+        registry.register("tar.lz", Box::new(FakeDecoder::new()));
+        registry.register("gz", Box::new(FakeDecoder::new()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let decoder = registry.for_file("Tool-1.0.TAR.LZ").unwrap();
+        decoder.decode("Tool-1.0.tar.lz", dir.path()).unwrap();
+        assert!(dir.path().join("decoded").is_file());
+
+        // tar.gz wins over gz, so the default decoder is used
+        let gz = dir.path().join("tool-1.0.tar.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz).unwrap(), Default::default());
+        encoder
+            .write_all(&tar_with_file("tool-1.0/README", b"hi"))
+            .unwrap();
+        encoder.finish().unwrap();
+        let decoder = registry.for_file("tool-1.0.tar.gz").unwrap();
+        decoder.decode(gz.to_str().unwrap(), dir.path()).unwrap();
+        assert!(dir.path().join("tool-1.0/README").is_file());
+        assert!(registry.for_file("tool-1.0.tar").is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_build_steps_are_reported_with_their_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("logs").join("tool_1.0.log");
+        for verbose in &[false, true] {
+            let _ = fs::remove_file(&log_path);
+            let log = BuildLog::new(&log_path, *verbose);
+            // what verbose builds mirror to the terminal
+            let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+            let mut ok = Command::new("sh");
+            ok.args(["-c", "echo checking"]);
+            log.run_step_with("configure", &mut ok, &mut stdout, &mut stderr)
+                .unwrap();
+
+            let mut failing = Command::new("sh");
+            failing.args(["-c", "echo compiling; echo broken >&2; exit 3"]);
+            let err = log
+                .run_step_with("make", &mut failing, &mut stdout, &mut stderr)
+                .err()
+                .unwrap();
+            if *verbose {
+                assert_eq!(stdout, b"checking\ncompiling\n");
+                assert_eq!(stderr, b"broken\n");
+            } else {
+                assert!(stdout.is_empty() && stderr.is_empty());
+            }
+            let err = err.downcast_ref::<BuildError>().unwrap();
+            assert_eq!(err.step, "make");
+            assert_eq!(err.status, Some(3));
+            assert_eq!(err.log, log_path);
+            assert_eq!(err.tail.last().unwrap(), "broken");
+
+            let contents = fs::read_to_string(&log_path).unwrap();
+            assert!(contents.starts_with("==> configure\nchecking\n==> make\n"));
+            assert!(contents.contains("compiling\n"));
+        }
+
+        let log = BuildLog::new(&log_path, false);
+        let err = log
+            .run_step("configure", &mut Command::new("./does-not-exist"))
+            .err()
+            .unwrap();
+        assert_eq!(err.downcast_ref::<BuildError>().unwrap().status, None);
+    }
+
+    #[test]
+    fn builds_are_installed_into_the_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("tool-1.0");
+        let install_dir = dir.path().join("programs").join("tool").join("1.0");
+        let log = BuildLog::new(&dir.path().join("tool_1.0.log"), false);
+        let install_dir_str = install_dir.display().to_string();
+
+        let steps = |runner: CommandRunner| -> Vec<(String, String, Vec<String>)> {
+            runner
+                .build_steps()
+                .iter()
+                .map(|(step, command)| {
+                    assert_eq!(command.get_current_dir(), Some(source_dir.as_path()));
+                    let args = command
+                        .get_args()
+                        .map(|arg| arg.to_string_lossy().to_string());
+                    let program = command.get_program().to_string_lossy().to_string();
+                    (step.clone(), program, args.collect())
+                })
+                .collect()
+        };
+
+        let flags = vec![String::from("--with-openssl")];
+        let make = steps(CommandRunner::Make(&source_dir, &install_dir, &log, &flags));
+        assert_eq!(make.len(), 3);
+        assert!(make[0].2.contains(&format!("--prefix={}", install_dir_str)));
+        assert_eq!(make[0].2.last().unwrap(), "--with-openssl");
+        assert!(make[1].2.is_empty());
+        assert_eq!(make[2].2, vec!["install"]);
+
+        let cmake = steps(CommandRunner::CMake(&source_dir, &install_dir, &log, &[]));
+        assert_eq!(cmake[0].1, "cmake");
+        assert!(cmake[0]
+            .2
+            .contains(&format!("-DCMAKE_INSTALL_PREFIX={}", install_dir_str)));
+        assert_eq!(cmake[2].2, vec!["--install", "build"]);
+
+        let meson = steps(CommandRunner::Meson(&source_dir, &install_dir, &log, &[]));
+        assert_eq!(meson[0].2[0], "setup");
+        assert!(meson[0]
+            .2
+            .contains(&format!("--prefix={}", install_dir_str)));
+        assert_eq!(meson[2].0, "meson install");
+
+        let cargo = steps(CommandRunner::Cargo(&source_dir, &install_dir, &log, &[]));
+        assert_eq!(cargo.len(), 1);
+        assert_eq!(
+            cargo[0].2,
+            vec!["install", "--path", ".", "--root", &install_dir_str]
+        );
+
+        assert!(steps(CommandRunner::Bin(&source_dir, &install_dir)).is_empty());
+        assert!(matches!(
+            InstallTypes::from_name("meson"),
+            Some(InstallTypes::Meson)
+        ));
+        assert!(InstallTypes::from_name("scons").is_none());
+    }
+
+    #[test]
+    fn variants_pick_the_flags_of_the_options() {
+        let option = |name: &str, default: bool, enabled: &str, disabled: &str| BuildOption {
+            name: name.to_string(),
+            default,
+            enabled: vec![enabled.to_string()],
+            disabled: vec![disabled.to_string()],
+        };
+        let options = vec![
+            option("ssl", true, "--with-openssl", "--without-openssl"),
+            option("optimizations", false, "--enable-optimizations", "-O0"),
+        ];
+
+        assert_eq!(
+            Variant::default().flags(&options).unwrap(),
+            vec!["--with-openssl", "-O0"]
+        );
+        let variant = Variant {
+            with: vec![String::from("optimizations")],
+            without: vec![String::from("ssl"), String::from("docs")],
+        };
+        let err = variant.flags(&options).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<UnknownBuildOptionError>().unwrap().name,
+            "docs"
+        );
+        let variant = variant.restricted_to(&options);
+        assert_eq!(
+            variant.flags(&options).unwrap(),
+            vec!["--without-openssl", "--enable-optimizations"]
+        );
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        let variables = [
+            ("PREFIX", String::from("/opt/tool")),
+            ("VERSION", String::from("1.0")),
+        ];
+
+        assert_eq!(
+            expand_variables("--prefix=$PREFIX", &variables),
+            "--prefix=/opt/tool"
+        );
+        assert_eq!(
+            expand_variables("${PREFIX}/lib/tool-${VERSION}.so", &variables),
+            "/opt/tool/lib/tool-1.0.so"
+        );
+        assert_eq!(
+            expand_variables("$HOME $PREFIXES ${VERSION", &variables),
+            "$HOME $PREFIXES ${VERSION"
+        );
+        assert_eq!(expand_variables("costs $5", &variables), "costs $5");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scripted_steps_stop_at_the_first_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("tool-1.0");
+        fs::create_dir_all(source_dir.join("sub")).unwrap();
+        let install_dir = dir.path().join("programs");
+        let log = BuildLog::new(&dir.path().join("tool_1.0.log"), false);
+        let step = |command: &str, args: &[&str]| InstallStep {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            dir: None,
+            env: Vec::new(),
+        };
+
+        let mut first = step(
+            "sh",
+            &["-c", "mkdir -p \"$DEST\" && pwd > \"$DEST/$VERSION\""],
+        );
+        first.dir = Some(String::from("sub"));
+        first.env = vec![(String::from("DEST"), String::from("$PREFIX/share"))];
+        let steps = vec![first, step("false", &[]), step("touch", &["$SRC_DIR/late"])];
+        let err = CommandRunner::Script(&source_dir, &install_dir, &log, &steps, "1.0")
+            .run()
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(install_dir.join("share").join("1.0")).unwrap(),
+            format!("{}\n", source_dir.join("sub").display())
+        );
+        assert_eq!(
+            err.downcast_ref::<BuildError>().unwrap().step,
+            "step 2 (false)"
+        );
+        assert!(!source_dir.join("late").exists());
+    }
+
+    #[test]
+    fn bin_install_copies_into_version_dir() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let source_dir = sage_home.path().join("downloads/node-v12.0.0");
+        fs::create_dir_all(source_dir.join("bin")).unwrap();
+        fs::write(source_dir.join("bin/node"), "node").unwrap();
+        let install_dir = sage_home.path().join("programs");
+        let root = version_dir(&install_dir, "node", "12.0.0");
+        fs::create_dir_all(root.parent().unwrap()).unwrap();
+
+        CommandRunner::Bin(&source_dir, &root).run().unwrap();
+
+        assert!(install_dir.join("node/12.0.0/bin/node").is_file());
+    }
+
+    #[test]
+    fn versions_are_activated_and_deactivated() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path();
+        fs::create_dir_all(version_dir(install_dir, "python", "3.7.0")).unwrap();
+        fs::create_dir_all(version_dir(install_dir, "python", "3.8.0")).unwrap();
+        assert_eq!(active_version(install_dir, "python"), None);
+
+        activate_version(install_dir, "python", "3.7.0").unwrap();
+        assert_eq!(active_version(install_dir, "python").unwrap(), "3.7.0");
+        activate_version(install_dir, "python", "3.8.0").unwrap();
+        assert_eq!(active_version(install_dir, "python").unwrap(), "3.8.0");
+        assert!(install_dir.join("python/current").is_dir());
+
+        deactivate_version(install_dir, "python").unwrap();
+        assert_eq!(active_version(install_dir, "python"), None);
+    }
+}
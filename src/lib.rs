@@ -1,87 +1,187 @@
+extern crate chrono;
 extern crate dirs;
 
+mod cache;
+mod database;
 mod errors;
 mod install_utils;
 mod packages;
 mod repositories;
+mod resolver;
+mod search;
+mod shims;
+mod versions;
 mod yml_parser;
 
-use errors::{NoVersionFoundError, NoVersionSpecifiedError, PackageNotFoundError};
-use install_utils::{Decoder, FileTypes, TarGzDecoder, TarXzDecoder};
+use cache::DEFAULT_CACHE_TTL;
+use database::{Database, InstalledPackage};
+use errors::{
+    CorruptedPackageError, InvalidRepoCommandError, MissingInstallInfoError, NoVersionFoundError,
+    NoVersionSpecifiedError, PackageNotFoundError, PackageNotInstalledError,
+    ShellNotSupportedError,
+};
+// the decoders are public so they can be benchmarked, reused and extended
+use install_utils::{
+    activate_version, active_version, deactivate_version, version_dir, walk_files, BuildLog,
+    FileTypes, Variant,
+};
+pub use install_utils::{
+    Decoder, DecoderRegistry, TarBz2Decoder, TarGzDecoder, TarXzDecoder, TarZstDecoder, ZipDecoder,
+};
 use packages::Package;
-use repositories::Repo;
-use yml_parser::{InstallInfo, MetadataParser, PackageParser};
+use repositories::{local_path, Repo, RepoList};
+use resolver::{resolve, PackageSource};
+use search::{search, CatalogEntry};
+use shims::{link_shims, path_snippet, unlink_shims};
+use versions::{compare_versions, VersionReq};
+use yml_parser::{IndexParser, InstallInfo, MetadataParser, PackageParser};
 
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Operating system whose packages are looked up in the repositories
+const HOST_OS: &str = "linux";
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let repo = Repo::new(
-        "Arcanum",
-        "https://raw.githubusercontent.com/rvillegasm/Arcanum/master/",
-        "linux",
-    );
+    run_with_decoders(config, &DecoderRegistry::default())
+}
+
+/// Runs the command of the config, unpacking the downloaded packages
+/// with the given decoders. Programs that embed `sage` can use it
+/// to add decoders for other kinds of archives.
+pub fn run_with_decoders(config: Config, decoders: &DecoderRegistry) -> Result<(), Box<dyn Error>> {
+    // every repository configured by the user, along with the keys they must be signed with
+    let mut repos = RepoList::load(&config.repos_file, HOST_OS, &config.keys_dir)?;
+    // the files fetched from them are kept for a while, or for good when working offline
+    repos.set_cache(&config.cache_dir, config.cache_ttl, config.offline);
 
     // Decide which command to use
     let command: &str = config.command.as_ref();
     match command {
         // get information about every version of said program
-        "info" => generic_info(&repo, &config.desired_pkg),
+        "info" => {
+            let repo = repos.find(config.get_desired_pkg())?;
+            generic_info(repo, config.get_desired_pkg())
+        }
         // get info about a specific version of the program
         "details" => {
-            // Check if the user specified a version or not
-            let version = match &config.desired_pkg_version {
-                Some(string) => string,
-                None => return Err(Box::new(NoVersionSpecifiedError)),
-            };
-            specific_info(&repo, &config.desired_pkg, version, true)?;
+            let name = config.get_desired_pkg();
+            let repo = repos.find(name)?;
+            // pick the version the user asked for, or the lts one
+            let version = resolve_version(repo, name, config.desired_pkg_version.as_deref())?;
+            specific_info(repo, name, &version, true)?;
             Ok(())
         }
         // just download a version of the program
         "download" => {
-            let version = match &config.desired_pkg_version {
-                Some(string) => string,
-                None => return Err(Box::new(NoVersionSpecifiedError)),
-            };
-            let name = &config.desired_pkg;
+            let name = config.get_desired_pkg();
+            let repo = repos.find(name)?;
+            let version = resolve_version(repo, name, config.desired_pkg_version.as_deref())?;
             // Create the package
-            let pkg = specific_info(&repo, name, version, false)?;
-            pkg.download(&config.download_dir)
+            let pkg = specific_info(repo, name, &version, false)?;
+            pkg.download(&config.download_dir, config.insecure, config.offline)
         }
         // download and install a version of the program
         "install" => {
+            let name = config.get_desired_pkg();
+            let repo = repos.find(name)?;
+            let version = resolve_version(repo, name, config.desired_pkg_version.as_deref())?;
+            install_with_dependencies(
+                &repos,
+                repo,
+                &config,
+                decoders,
+                name,
+                &version,
+                &config.variant,
+            )
+        }
+        // remove an installed version of the program
+        "uninstall" => {
+            let db = Database::new(&config.db_dir);
+            let versions = db.versions_of(config.get_desired_pkg())?;
+            // pick the requested version, or the only one that is installed
+            let record = match &config.desired_pkg_version {
+                Some(version) => versions
+                    .into_iter()
+                    .find(|pkg| pkg.get_version() == version),
+                None if versions.len() > 1 => return Err(Box::new(NoVersionSpecifiedError)),
+                None => versions.into_iter().next(),
+            };
+            let record = match record {
+                Some(record) => record,
+                None => return Err(Box::new(PackageNotInstalledError)),
+            };
+            let kept = uninstall(&db, &config.install_dir, &config.bin_dir, &record)?;
+
+            println!(
+                "Done! Package {}@{} successfully uninstalled from your system.",
+                record.get_name(),
+                record.get_version()
+            );
+            if kept > 0 {
+                println!(
+                    "{} file(s) were kept because they belong to other packages.",
+                    kept
+                );
+            }
+            Ok(())
+        }
+        // switch the active version of an installed program
+        "use" => {
             let version = match &config.desired_pkg_version {
                 Some(string) => string,
                 None => return Err(Box::new(NoVersionSpecifiedError)),
             };
-            let name = &config.desired_pkg;
-            // create the package
-            let pkg = specific_info(&repo, name, version, false)?;
-            // download
-            pkg.download(&config.download_dir)?;
-            // analyze if a decoder and the sage_home_path is needed or not
-            match pkg.get_file_type() {
-                // TODO: find a way of eliminating this code repetition (using polymorphism or trait objects)
-                FileTypes::TarGz => {
-                    let decoder = Some(TarGzDecoder::new());
-                    pkg.install(
-                        &config.download_dir,
-                        config.download_dir.parent().unwrap().to_str(),
-                        decoder,
-                    )
-                }
-                FileTypes::TarXz => {
-                    let decoder = Some(TarXzDecoder::new());
-                    pkg.install(
-                        &config.download_dir,
-                        config.download_dir.parent().unwrap().to_str(),
-                        decoder,
-                    )
-                }
+            let name = config.get_desired_pkg();
+            let db = Database::new(&config.db_dir);
+            if db.get(name, version)?.is_none() {
+                return Err(Box::new(PackageNotInstalledError));
             }
+            activate_version(&config.install_dir, name, version)?;
+            relink_shims(&config.bin_dir, &config.install_dir, name)?;
+
+            println!("Now using {}@{}.", name, version);
+            Ok(())
+        }
+        // show every installed package
+        "list" => list_installed(&repos, &config),
+        // print the shell snippet that puts the shims on the PATH
+        "env" => {
+            // use the shell the user is running unless another one was specified
+            let shell = match config.args.first() {
+                Some(shell) => shell.clone(),
+                None => env::var("SHELL").unwrap_or_default(),
+            };
+            let shell = Path::new(&shell)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let snippet = match path_snippet(shell, &config.bin_dir) {
+                Some(snippet) => snippet,
+                None => return Err(Box::new(ShellNotSupportedError)),
+            };
+
+            println!("# Add the following line to the configuration file of your shell:");
+            println!("{}", snippet);
+            Ok(())
+        }
+        // move the installed packages to their latest (or lts) version
+        "upgrade" => upgrade(&repos, &config, decoders),
+        // manage the repositories in which the packages are looked up
+        "repo" => manage_repos(repos, &config),
+        // look for packages in the index of every repository
+        "search" => search_repos(&repos, &config.args[0]),
+        // fetch again every cached repository file
+        "update-index" | "refresh" => {
+            let refreshed = repos.refresh()?;
+            println!("Done! {} cached file(s) refreshed.", refreshed);
+            Ok(())
         }
         // This will never happen, it's just here to exaust the match options
         _ => panic!("Could not match on the specified command"),
@@ -101,21 +201,16 @@ fn generic_info(repo: &Repo, program_name: &str) -> Result<(), Box<dyn Error>> {
     let parser = MetadataParser::new(&metadata)?;
 
     // Calculates the versions
-    let versions = match parser.get_versions() {
-        Some(vec) => vec,
-        None => return Err(Box::new(NoVersionFoundError)),
-    };
+    let versions = parser.get_version_names();
+    if versions.is_empty() {
+        return Err(Box::new(NoVersionFoundError));
+    }
 
     println!("Package: {}", program_name);
     println!("Available versions: ");
     // And print them
-    for i in 0..versions.len() {
-        println!(
-            "  - {}",
-            versions[i]
-                .as_str()
-                .expect("Error trying to access the versions of the package")
-        );
+    for version in &versions {
+        println!("  - {}", version);
     }
     // The unwraps here are because, for the moment,
     // a package will always have an LTS and a Latest value
@@ -146,13 +241,31 @@ fn specific_info(
         None => return Err(Box::new(PackageNotFoundError)),
     };
 
-    // With how the repo (Arcanum) is designed, these unwraps will never fail
-    let pkg_version = parser.get_version().unwrap();
-    let pkg_url = parser.get_ulr().unwrap();
-    let pkg_type = parser.get_file_type().unwrap();
-    let pkg_file = parser.get_file_name().unwrap();
-    let pkg_install_type = parser.get_installation_info(InstallInfo::Type).unwrap();
-    let pkg_install_target = parser.get_installation_info(InstallInfo::Target).unwrap();
+    // every package must say which version it is and where to get it from
+    let pkg_version = parser.get_version().ok_or(CorruptedPackageError {
+        field: String::from("version"),
+    })?;
+    let pkg_url = parser.get_ulr().ok_or(CorruptedPackageError {
+        field: String::from("url"),
+    })?;
+    // the type is detected after downloading when it is missing
+    let pkg_type = parser
+        .get_file_type()
+        .unwrap_or_else(|| String::from("unknown"));
+    // git packages are cloned into a directory named after the version by default
+    let pkg_file = parser
+        .get_file_name()
+        .unwrap_or_else(|| format!("{}-{}", pkg_name, pkg_version));
+    let pkg_install_steps = parser.get_install_steps();
+    let pkg_install_type = install_type(&parser, &pkg_type, !pkg_install_steps.is_empty())?;
+    // the target is checked once the real type of the file is known,
+    // since only single executables and git repositories can go without one
+    let pkg_install_target = parser
+        .get_installation_info(InstallInfo::Target)
+        .unwrap_or_default();
+    let pkg_build_options = parser.get_build_options();
+    let pkg_sha256 = parser.get_sha256();
+    let pkg_sha512 = parser.get_sha512();
 
     if print_out_info {
         println!("Package: {}", pkg_name);
@@ -160,18 +273,813 @@ fn specific_info(
         println!("Download Url: {}", pkg_url);
         println!("File Type: {}", pkg_type);
         println!("File Name: {}", pkg_file);
+        if let Some(tag) = parser.get_tag() {
+            println!("Tag: {}", tag);
+        }
+        if let Some(commit) = parser.get_commit() {
+            println!("Commit: {}", commit);
+        }
         println!("Installation: {}", pkg_install_type);
+        for (i, step) in pkg_install_steps.iter().enumerate() {
+            println!("  {}. {} {}", i + 1, step.command, step.args.join(" "));
+        }
+        if !pkg_build_options.is_empty() {
+            println!("Build Options:");
+        }
+        for option in &pkg_build_options {
+            let default = if option.default { "on" } else { "off" };
+            println!("  {} ({} by default)", option.name, default);
+        }
+        if let Some(sha256) = &pkg_sha256 {
+            println!("SHA-256: {}", sha256);
+        }
+        if let Some(sha512) = &pkg_sha512 {
+            println!("SHA-512: {}", sha512);
+        }
+        let dependencies: Vec<String> = parser
+            .get_dependencies()?
+            .iter()
+            .map(|(name, req)| format!("{} {}", name, req))
+            .collect();
+        if !dependencies.is_empty() {
+            println!("Dependencies: {}", dependencies.join(", "));
+        }
+    }
+
+    let mut pkg = Package::new(
+        &pkg_name,
+        &pkg_version,
+        &pkg_url,
+        &pkg_type,
+        &pkg_file,
+        &pkg_install_type,
+        &pkg_install_target,
+    )?;
+    pkg.set_checksums(pkg_sha256.as_deref(), pkg_sha512.as_deref());
+    pkg.set_install_steps(pkg_install_steps);
+    pkg.set_build_options(pkg_build_options);
+    pkg.set_git_revision(parser.get_tag().as_deref(), parser.get_commit().as_deref());
+
+    Ok(pkg)
+}
+
+/// Returns the install type of a package. Single executables don't need one,
+/// they are copied to *bin/<target>* or *bin/<name>*, and packages that list
+/// their own steps are installed by running them.
+/// Files of an unknown type are taken for single executables until their
+/// type is detected, and `Package::install` rejects them if they are not.
+///
+/// # Errors
+/// The function will return an error if the install type is missing
+/// and it can't be left out.
+fn install_type(
+    parser: &PackageParser,
+    file_type: &str,
+    has_steps: bool,
+) -> Result<String, MissingInstallInfoError> {
+    match parser.get_installation_info(InstallInfo::Type) {
+        Some(install_type) => Ok(install_type),
+        None if has_steps => Ok(String::from("script")),
+        None => match FileTypes::from_name(file_type) {
+            FileTypes::Raw | FileTypes::Unknown => Ok(String::from("bin")),
+            _ => Err(MissingInstallInfoError {
+                field: String::from("type"),
+            }),
+        },
+    }
+}
+
+/// Works out which version of a package the user asked for, which can be
+/// an exact version, *latest*, *lts* or a version requirement like *^3.7*.
+/// Requirements are met by the newest matching version, and when no version
+/// is given the lts one is used, or the latest one if there is no lts.
+///
+/// # Errors
+/// The function will return an error if no version matches what the user asked for.
+/// Check out the documentation for `Repo::get_program_metadata`, `MetadataParser::new`
+/// and `VersionReq::parse` to find out other reasons for this function to fail.
+fn resolve_version(
+    repo: &Repo,
+    program_name: &str,
+    requested: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let metadata = repo.get_program_metadata(program_name)?;
+    let parser = MetadataParser::new(&metadata)?;
+    let versions = parser.get_version_names();
+
+    let version = match requested {
+        None => parser
+            .get_lts_version()
+            .or_else(|| parser.get_latest_version()),
+        Some("lts") => parser.get_lts_version(),
+        Some("latest") => parser.get_latest_version(),
+        Some(exact) if versions.iter().any(|version| version == exact) => Some(exact.to_string()),
+        Some(req) => VersionReq::parse(req)?
+            .newest_match(versions.iter().map(String::as_str))
+            .map(String::from),
+    };
+    match version {
+        Some(version) => Ok(version),
+        None => Err(Box::new(NoVersionFoundError)),
+    }
+}
+
+/// Downloads and installs a version of a package, recording it
+/// in the local database once everything went fine.
+/// Builds from source are logged to *logs/<name>_<version>.log*,
+/// and get the flags of the build options picked by the variant.
+///
+/// # Errors
+/// Check out the documentation for `specific_info`, `Package::build_flags`,
+/// `Package::download`, `Package::install` and `register_install`
+/// to find out the reasons for this function to fail.
+fn install(
+    repo: &Repo,
+    config: &Config,
+    decoders: &DecoderRegistry,
+    name: &str,
+    version: &str,
+    variant: &Variant,
+) -> Result<(), Box<dyn Error>> {
+    // create the package
+    let mut pkg = specific_info(repo, name, version, false)?;
+    // fail on unknown build options before downloading anything
+    let build_flags = pkg.build_flags(variant)?;
+    // every version of a package gets its own install directory
+    let install_root = version_dir(&config.install_dir, name, pkg.get_version());
+    fs::create_dir_all(install_root.parent().unwrap())?;
+    // every install starts a new build log
+    let log_file = config
+        .logs_dir
+        .join(format!("{}_{}.log", name, pkg.get_version()));
+    if log_file.is_file() {
+        fs::remove_file(&log_file)?;
+    }
+    let build_log = BuildLog::new(&log_file, config.verbose);
+    replace_version_dir(&install_root, || {
+        // download
+        pkg.download(&config.download_dir, config.insecure, config.offline)?;
+        // the contents of the file tell its real type
+        pkg.detect_file_type(&config.download_dir)?;
+        // unpack it with the decoder for its type, and install it
+        pkg.install(
+            &config.download_dir,
+            Some(&install_root),
+            decoders,
+            &build_log,
+            &build_flags,
+        )
+    })?;
+    // keep track of what has just been installed, and how it was built
+    register_install(repo, config, name, &pkg, variant)?;
+    sync_active_version(
+        &config.install_dir,
+        &config.bin_dir,
+        name,
+        pkg.get_version(),
+    )
+}
+
+/// Runs the build of a version of a package into its install directory,
+/// starting from scratch. A previous install of the same version is moved
+/// aside while building, and it is put back in place if the build fails,
+/// so that a failed reinstall doesn't break a working version.
+///
+/// # Errors
+/// Returns the error of the build itself, and check out the documentation for
+/// `std::fs::rename` and `std::fs::remove_dir_all` to find out other reasons
+/// for this function to fail.
+fn replace_version_dir<F>(install_root: &Path, build: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn Error>>,
+{
+    let version = install_root.file_name().unwrap().to_string_lossy();
+    let previous = install_root.with_file_name(format!(".{}.previous", version));
+    // a leftover of an interrupted reinstall
+    if previous.exists() {
+        if install_root.exists() {
+            fs::remove_dir_all(&previous)?;
+        } else {
+            fs::rename(&previous, install_root)?;
+        }
+    }
+    if install_root.exists() {
+        fs::rename(install_root, &previous)?;
+    }
+
+    match build() {
+        Ok(()) => {
+            if previous.exists() {
+                fs::remove_dir_all(&previous)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if install_root.exists() {
+                fs::remove_dir_all(install_root)?;
+            }
+            if previous.exists() {
+                fs::rename(&previous, install_root)?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Activates a freshly installed version of a package when it is the first one,
+/// and refreshes the shims when it is the active one, since a reinstall
+/// may have changed the executables it exposes.
+///
+/// # Errors
+/// Check out the documentation for `activate_version` and `relink_shims`.
+fn sync_active_version(
+    install_dir: &Path,
+    bin_dir: &Path,
+    name: &str,
+    version: &str,
+) -> Result<(), Box<dyn Error>> {
+    match active_version(install_dir, name) {
+        None => activate_version(install_dir, name, version)?,
+        Some(active) if active == version => (),
+        Some(_) => return Ok(()),
+    }
+
+    relink_shims(bin_dir, install_dir, name)
+}
+
+/// Information about the packages of the configured repositories
+/// and the installed ones, as needed by the dependency resolver
+struct RepoSource<'a> {
+    repos: &'a RepoList,
+    db: Database,
+}
+
+impl<'a> PackageSource for RepoSource<'a> {
+    fn available_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let metadata = self.repos.find(name)?.get_program_metadata(name)?;
+        let parser = MetadataParser::new(&metadata)?;
+        Ok(parser.get_version_names())
+    }
+
+    fn installed_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let versions = self
+            .db
+            .versions_of(name)?
+            .iter()
+            .map(|pkg| pkg.get_version().to_string())
+            .collect();
+
+        Ok(versions)
     }
 
-    Ok(Package::new(
-        pkg_name,
-        pkg_version,
-        pkg_url,
-        pkg_type,
-        pkg_file,
-        pkg_install_type,
-        pkg_install_target,
-    )?)
+    fn dependencies(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let pkg_data = self.repos.find(name)?.get_program_package(name, version)?;
+        let parser = PackageParser::new(&pkg_data)?;
+        Ok(parser.get_dependencies()?)
+    }
+}
+
+/// Installs a version of a package from the given repo, after installing
+/// every dependency that is missing, in the order in which they depend on each other.
+/// Only the package itself is built with the given variant, the dependencies
+/// keep the defaults of their build options.
+///
+/// # Errors
+/// Check out the documentation for `resolve` and `install`
+/// to find out the reasons for this function to fail.
+fn install_with_dependencies(
+    repos: &RepoList,
+    repo: &Repo,
+    config: &Config,
+    decoders: &DecoderRegistry,
+    name: &str,
+    version: &str,
+    variant: &Variant,
+) -> Result<(), Box<dyn Error>> {
+    let source = RepoSource {
+        repos,
+        db: Database::new(&config.db_dir),
+    };
+    let plan = resolve(&source, name, version)?;
+    // the package itself always comes last
+    let dependencies = &plan[..plan.len() - 1];
+    if !dependencies.is_empty() {
+        let names: Vec<String> = dependencies
+            .iter()
+            .map(|(name, version)| format!("{}@{}", name, version))
+            .collect();
+        println!("Installing the dependencies first: {}", names.join(", "));
+    }
+    for (dependency, dependency_version) in dependencies {
+        install(
+            repos.find(dependency)?,
+            config,
+            decoders,
+            dependency,
+            dependency_version,
+            &Variant::default(),
+        )?;
+    }
+
+    install(repo, config, decoders, name, version, variant)
+}
+
+/// Moves the installed packages (or just the one specified by the user)
+/// to the latest version available in the repo, or to the lts one when
+/// following the lts track. The new version is built with the same build
+/// options as the old one, as long as it still has them. The old version
+/// of a package is only removed after the new one has been successfully installed.
+///
+/// # Errors
+/// The function will return an error if the specified package is not installed.
+/// Check out the documentation for `install` and `uninstall` to find out
+/// other reasons for this function to fail.
+fn upgrade(
+    repos: &RepoList,
+    config: &Config,
+    decoders: &DecoderRegistry,
+) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_dir);
+    let installed = match &config.desired_pkg {
+        Some(name) => db.versions_of(name)?,
+        None => db.list()?,
+    };
+    if installed.is_empty() && config.desired_pkg.is_some() {
+        return Err(Box::new(PackageNotInstalledError));
+    }
+
+    // only the newest installed version of each package gets upgraded
+    let mut newest: BTreeMap<String, InstalledPackage> = BTreeMap::new();
+    for pkg in installed {
+        let is_newer = match newest.get(pkg.get_name()) {
+            Some(other) => {
+                compare_versions(pkg.get_version(), other.get_version()) == Ordering::Greater
+            }
+            None => true,
+        };
+        if is_newer {
+            newest.insert(pkg.get_name().to_string(), pkg);
+        }
+    }
+
+    for (name, current) in newest {
+        let repo = repo_of(repos, &current)?;
+        let metadata = repo.get_program_metadata(&name)?;
+        let parser = MetadataParser::new(&metadata)?;
+        let target = if config.lts {
+            parser.get_lts_version()
+        } else {
+            parser.get_latest_version()
+        };
+        let target = match target {
+            Some(version) => version,
+            None => return Err(Box::new(NoVersionFoundError)),
+        };
+
+        let target = target.as_str();
+        if compare_versions(target, current.get_version()) != Ordering::Greater {
+            println!("{}@{} is already up to date.", name, current.get_version());
+            continue;
+        }
+        println!(
+            "Upgrading {} from {} to {}...",
+            name,
+            current.get_version(),
+            target
+        );
+        // rebuild with the options the old version was built with
+        let pkg = specific_info(repo, &name, target, false)?;
+        let variant = current.get_variant().restricted_to(pkg.get_build_options());
+        let dropped: Vec<&str> = current
+            .get_variant()
+            .with
+            .iter()
+            .chain(current.get_variant().without.iter())
+            .filter(|option| !variant.with.contains(option) && !variant.without.contains(option))
+            .map(String::as_str)
+            .collect();
+        if !dropped.is_empty() {
+            println!(
+                "{}@{} no longer has the build options: {}",
+                name,
+                target,
+                dropped.join(", ")
+            );
+        }
+        install_with_dependencies(repos, repo, config, decoders, &name, target, &variant)?;
+        // the new version is in place, so the old one can go away
+        let was_active =
+            active_version(&config.install_dir, &name).as_deref() == Some(current.get_version());
+        if was_active {
+            activate_version(&config.install_dir, &name, target)?;
+            relink_shims(&config.bin_dir, &config.install_dir, &name)?;
+        }
+        uninstall(&db, &config.install_dir, &config.bin_dir, &current)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the repository an installed package came from, or the one that
+/// provides it now if that repository is no longer configured.
+///
+/// # Errors
+/// Check out the documentation for `RepoList::find`.
+fn repo_of<'a>(repos: &'a RepoList, pkg: &InstalledPackage) -> Result<&'a Repo, Box<dyn Error>> {
+    match repos.get(pkg.get_repo()) {
+        Some(repo) => Ok(repo),
+        None => repos.find(pkg.get_name()),
+    }
+}
+
+/// Lists, adds or removes the repositories in which the packages are looked up,
+/// saving the changes to the repositories config file.
+///
+/// # Errors
+/// The function will return an error if the subcommand or its arguments are not valid.
+/// Check out the documentation for `RepoList::add`, `RepoList::remove`
+/// and `RepoList::save` to find out other reasons for this function to fail.
+fn manage_repos(mut repos: RepoList, config: &Config) -> Result<(), Box<dyn Error>> {
+    let args: Vec<&str> = config.args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] | ["list"] => {
+            let mut rows = vec![vec![
+                String::from("NAME"),
+                String::from("PRIORITY"),
+                String::from("URL"),
+            ]];
+            for repo in repos.iter() {
+                rows.push(vec![
+                    repo.get_name().to_string(),
+                    repo.get_priority().to_string(),
+                    repo.get_base_url().to_string(),
+                ]);
+            }
+            print_table(&rows);
+            return Ok(());
+        }
+        ["add", name, url] => {
+            // local repositories are stored with an absolute path
+            let url = match local_path(url)? {
+                Some(path) if path.is_relative() => env::current_dir()?.join(path),
+                _ => PathBuf::from(url),
+            };
+            let url = url.to_string_lossy();
+            repos.add(name, &url, config.priority, HOST_OS)?;
+            println!(
+                "Repository {} added with priority {}.",
+                name, config.priority
+            );
+        }
+        ["remove", name] => {
+            repos.remove(name)?;
+            println!("Repository {} removed.", name);
+        }
+        _ => return Err(Box::new(InvalidRepoCommandError)),
+    }
+
+    repos.save(&config.repos_file)
+}
+
+/// Prints the packages of every repository whose name, description
+/// or tags match the search term, best matches first.
+/// Repositories without an index are skipped.
+///
+/// # Errors
+/// Check out the documentation for `Repo::get_index` and `IndexParser::new`
+/// to find out the reasons for this function to fail.
+fn search_repos(repos: &RepoList, term: &str) -> Result<(), Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for repo in repos.iter() {
+        let index = match repo.get_index() {
+            Ok(index) => index,
+            Err(e) if e.is::<PackageNotFoundError>() => continue,
+            Err(e) => return Err(e),
+        };
+        let parser = IndexParser::new(&index)?;
+        if let Some(packages) = parser.get_packages() {
+            entries.extend(
+                packages
+                    .iter()
+                    .filter_map(|entry| CatalogEntry::from_yaml(repo.get_name(), entry)),
+            );
+        }
+    }
+
+    let matches = search(&entries, term);
+    if matches.is_empty() {
+        println!("No packages matched {}.", term);
+        return Ok(());
+    }
+    let mut rows = vec![vec![
+        String::from("NAME"),
+        String::from("LATEST"),
+        String::from("REPO"),
+        String::from("DESCRIPTION"),
+    ]];
+    for entry in matches {
+        rows.push(vec![
+            entry.name.clone(),
+            entry.latest.clone(),
+            entry.repo.clone(),
+            entry.description.clone(),
+        ]);
+    }
+    print_table(&rows);
+
+    Ok(())
+}
+
+/// Records a freshly installed package in the local database, along with
+/// every file that its installation placed under the programs directory
+/// and the variant it was built with.
+///
+/// # Errors
+/// Check out the documentation for `walk_files` and `Database::add`
+/// to find out the reasons for this function to fail.
+fn register_install(
+    repo: &Repo,
+    config: &Config,
+    name: &str,
+    pkg: &Package,
+    variant: &Variant,
+) -> Result<(), Box<dyn Error>> {
+    let install_root = version_dir(&config.install_dir, name, pkg.get_version());
+    let files = walk_files(&install_root)?
+        .iter()
+        .filter_map(|file| file.strip_prefix(&config.install_dir).ok())
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+
+    // the package is recorded under the name it has in the repo
+    let mut record = InstalledPackage::new(
+        name,
+        pkg.get_version(),
+        repo.get_name(),
+        &pkg.get_install_type().to_string(),
+        pkg.get_install_target(),
+        files,
+    );
+    record.set_variant(variant.clone());
+    Database::new(&config.db_dir).add(&record)
+}
+
+/// Points the shims of a package to its active version, letting the user
+/// know about the executables that could not get one.
+///
+/// # Errors
+/// Check out the documentation for `link_shims`.
+fn relink_shims(bin_dir: &Path, install_dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    for executable in link_shims(bin_dir, install_dir, name)? {
+        eprintln!(
+            "Warning: {} is already provided by another package, so {} won't expose it.",
+            executable, name
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes every file placed by an installed package, along with the
+/// directories that end up empty, and forgets about it in the database.
+/// Files that are also owned by another installed package are kept,
+/// and their amount is returned. If the removed version was the active one,
+/// the newest remaining version of the package takes its place.
+///
+/// # Errors
+/// Check out the documentation for `Database::list`, `Database::remove`
+/// and `std::fs::remove_file` to find out the reasons for this function to fail.
+fn uninstall(
+    db: &Database,
+    install_dir: &Path,
+    bin_dir: &Path,
+    record: &InstalledPackage,
+) -> Result<usize, Box<dyn Error>> {
+    // every file claimed by the rest of the installed packages
+    let mut foreign_files = HashSet::new();
+    for pkg in db.list()? {
+        if pkg.get_name() == record.get_name() && pkg.get_version() == record.get_version() {
+            continue;
+        }
+        foreign_files.extend(pkg.get_files().iter().cloned());
+    }
+
+    let mut kept = 0;
+    for file in record.get_files() {
+        if foreign_files.contains(file) {
+            kept += 1;
+            continue;
+        }
+        // never touch anything outside of the programs directory
+        let relative = Path::new(file);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            continue;
+        }
+        let path = install_dir.join(relative);
+        // symlink_metadata so that dangling symlinks get removed as well
+        if fs::symlink_metadata(&path).is_err() {
+            continue;
+        }
+        fs::remove_file(&path)?;
+
+        // clean up the directories that were left empty
+        let mut dir = path.parent();
+        while let Some(parent) = dir {
+            if parent == install_dir || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+    db.remove(record.get_name(), record.get_version())?;
+
+    let name = record.get_name();
+    if active_version(install_dir, name).as_deref() == Some(record.get_version()) {
+        let remaining = db.versions_of(name)?;
+        let newest = remaining
+            .iter()
+            .map(|pkg| pkg.get_version())
+            .max_by(|a, b| compare_versions(a, b));
+        match newest {
+            Some(version) => {
+                activate_version(install_dir, name, version)?;
+                relink_shims(bin_dir, install_dir, name)?;
+            }
+            None => {
+                unlink_shims(bin_dir, install_dir, name)?;
+                deactivate_version(install_dir, name)?;
+                // nothing is left of the package
+                let _ = fs::remove_dir(install_dir.join(name));
+            }
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Prints every installed package along with its version, install type,
+/// install date and disk usage, either as a table or as json.
+/// When only the outdated packages are requested, the latest version
+/// of each one is looked up in the repo as well.
+///
+/// # Errors
+/// Check out the documentation for `Database::list`, `Repo::get_program_metadata`
+/// and `MetadataParser::new` to find out the reasons for this function to fail.
+fn list_installed(repos: &RepoList, config: &Config) -> Result<(), Box<dyn Error>> {
+    let db = Database::new(&config.db_dir);
+
+    let mut rows = Vec::new();
+    for pkg in db.list()? {
+        let latest = if config.outdated {
+            let metadata = repo_of(repos, &pkg)?.get_program_metadata(pkg.get_name())?;
+            let parser = MetadataParser::new(&metadata)?;
+            match parser.get_latest_version() {
+                Some(latest)
+                    if compare_versions(&latest, pkg.get_version()) == Ordering::Greater =>
+                {
+                    Some(latest)
+                }
+                _ => continue,
+            }
+        } else {
+            None
+        };
+        let size = pkg.get_disk_usage(&config.install_dir);
+        let active = active_version(&config.install_dir, pkg.get_name()).as_deref()
+            == Some(pkg.get_version());
+        rows.push((pkg, size, active, latest));
+    }
+
+    if config.json {
+        // one json object per package
+        let objects: Vec<String> = rows
+            .iter()
+            .map(|(pkg, size, active, latest)| {
+                let mut fields = vec![
+                    format!("\"name\": {}", json_string(pkg.get_name())),
+                    format!("\"version\": {}", json_string(pkg.get_version())),
+                    format!("\"install_type\": {}", json_string(pkg.get_install_type())),
+                    format!(
+                        "\"install_target\": {}",
+                        json_string(pkg.get_install_target())
+                    ),
+                    format!("\"installed_at\": {}", json_string(pkg.get_installed_at())),
+                    format!("\"size\": {}", size),
+                    format!("\"active\": {}", active),
+                ];
+                if let Some(latest) = latest {
+                    fields.push(format!("\"latest\": {}", json_string(latest)));
+                }
+                format!("  {{{}}}", fields.join(", "))
+            })
+            .collect();
+        if objects.is_empty() {
+            println!("[]");
+        } else {
+            println!("[\n{}\n]", objects.join(",\n"));
+        }
+        return Ok(());
+    }
+
+    if rows.is_empty() {
+        println!("No packages to show.");
+        return Ok(());
+    }
+    let mut table = vec![vec![
+        String::from("NAME"),
+        String::from("VERSION"),
+        String::from("ACTIVE"),
+        String::from("TYPE"),
+        String::from("INSTALLED"),
+        String::from("SIZE"),
+    ]];
+    if config.outdated {
+        table[0].push(String::from("LATEST"));
+    }
+    for (pkg, size, active, latest) in rows {
+        // show the install date in the local time, down to the minute
+        let installed_at = match chrono::DateTime::parse_from_rfc3339(pkg.get_installed_at()) {
+            Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+            Err(_) => pkg.get_installed_at().to_string(),
+        };
+        let mut row = vec![
+            pkg.get_name().to_string(),
+            pkg.get_version().to_string(),
+            String::from(if active { "*" } else { "" }),
+            pkg.get_install_type().to_string(),
+            installed_at,
+            format_size(size),
+        ];
+        if let Some(latest) = latest {
+            row.push(latest);
+        }
+        table.push(row);
+    }
+    print_table(&table);
+
+    Ok(())
+}
+
+/// Prints the rows as left-aligned columns
+fn print_table(rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if i == widths.len() {
+                widths.push(0);
+            }
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Formats an amount of bytes as a human readable size, like 4.2 MiB
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Quotes and escapes a string so that it can be embedded in a json document
+fn json_string(str: &str) -> String {
+    let mut out = String::with_capacity(str.len() + 2);
+    out.push('"');
+    for c in str.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
 
 /// Configuration data structure that holds
@@ -179,9 +1087,25 @@ fn specific_info(
 pub struct Config {
     download_dir: PathBuf,
     install_dir: PathBuf,
+    db_dir: PathBuf,
+    bin_dir: PathBuf,
+    keys_dir: PathBuf,
+    repos_file: PathBuf,
+    cache_dir: PathBuf,
+    logs_dir: PathBuf,
+    cache_ttl: Duration, // how long the cached repository files stay fresh
     command: String,
-    desired_pkg: String,
+    desired_pkg: Option<String>,
     desired_pkg_version: Option<String>,
+    args: Vec<String>, // arguments of the commands that don't work on a package
+    outdated: bool,    // only show the packages with newer versions available
+    json: bool,        // machine-readable output
+    lts: bool,         // follow the lts versions instead of the latest ones
+    insecure: bool,    // allow downloads that can't be verified
+    priority: i64,     // priority of the repository being added
+    offline: bool,     // only use what is already cached or downloaded
+    verbose: bool,     // show the output of the builds while they run
+    variant: Variant,  // build options enabled or disabled by the user
 }
 
 // Config helper functions
@@ -189,12 +1113,21 @@ pub struct Config {
 /// Checks if the specified command is supported by sage or not
 fn parse_commands(command: String) -> Result<String, &'static str> {
     // A set containing all the valid commands
-    const COMMANDS_QUANTITY: usize = 4; // NUMBER OF COMMANDS
+    const COMMANDS_QUANTITY: usize = 13; // NUMBER OF COMMANDS
     let mut command_set: HashSet<&str> = HashSet::with_capacity(COMMANDS_QUANTITY);
     command_set.insert("info");
     command_set.insert("details");
     command_set.insert("download");
     command_set.insert("install");
+    command_set.insert("uninstall");
+    command_set.insert("list");
+    command_set.insert("upgrade");
+    command_set.insert("use");
+    command_set.insert("env");
+    command_set.insert("repo");
+    command_set.insert("update-index");
+    command_set.insert("refresh");
+    command_set.insert("search");
 
     // Check if the specified command is valid
     let command_str: &str = command.as_ref();
@@ -205,6 +1138,76 @@ fn parse_commands(command: String) -> Result<String, &'static str> {
     }
 }
 
+/// Checks if the specified command works on a package or not
+fn takes_package(command: &str) -> bool {
+    !matches!(
+        command,
+        "list" | "env" | "repo" | "update-index" | "refresh" | "search"
+    )
+}
+
+/// Returns how many arguments, besides the package, the command accepts
+fn max_args(command: &str) -> usize {
+    match command {
+        "env" => 1,
+        "repo" => 3,
+        "search" => 1,
+        _ => 0,
+    }
+}
+
+/// Checks if the specified command needs a package to work on,
+/// or if it can work on every installed package as well
+fn requires_package(command: &str) -> bool {
+    match command {
+        "upgrade" => false,
+        command => takes_package(command),
+    }
+}
+
+/// Checks if the specified option is supported by the command,
+/// and if it is, turns it on in the `Config`.
+/// Options that take a value read it from the remaining arguments.
+fn parse_option<I: Iterator<Item = String>>(
+    option: &str,
+    args: &mut I,
+    config: &mut Config,
+) -> Result<(), &'static str> {
+    match (config.command.as_str(), option) {
+        ("repo", "--priority") => {
+            config.priority = match args.next().map(|value| value.parse()) {
+                Some(Ok(priority)) => priority,
+                _ => return Err("The priority must be a number"),
+            }
+        }
+        ("info", "--offline")
+        | ("details", "--offline")
+        | ("download", "--offline")
+        | ("install", "--offline")
+        | ("upgrade", "--offline")
+        | ("list", "--offline")
+        | ("search", "--offline") => config.offline = true,
+        ("list", "--outdated") => config.outdated = true,
+        ("list", "--json") => config.json = true,
+        ("upgrade", "--lts") => config.lts = true,
+        ("download", "--insecure") | ("install", "--insecure") | ("upgrade", "--insecure") => {
+            config.insecure = true
+        }
+        ("install", "--verbose") | ("upgrade", "--verbose") => config.verbose = true,
+        ("install", "--with") => match args.next() {
+            Some(option) => config.variant.with.push(option),
+            None => return Err("No build option was specified"),
+        },
+        ("install", "--without") => match args.next() {
+            Some(option) => config.variant.without.push(option),
+            None => return Err("No build option was specified"),
+        },
+        _ => return Err("The specified option is not valid"),
+    }
+
+    Ok(())
+}
+
 /// Checks if the user specified a version with the package or not
 fn parse_desired_pkg(pkg: String) -> (String, Option<String>) {
     // Check if the package contains an '@'
@@ -228,7 +1231,7 @@ fn parse_desired_pkg(pkg: String) -> (String, Option<String>) {
 impl Config {
     /// Wraps the environment arguments as a `Config`
     /// data structure.
-    pub fn new(mut args: env::Args) -> Result<Config, &'static str> {
+    pub fn new<I: Iterator<Item = String>>(mut args: I) -> Result<Config, &'static str> {
         // ignore the name with which sage was called by the user
         args.next();
 
@@ -239,13 +1242,6 @@ impl Config {
         };
         let command = parse_commands(command)?;
 
-        // Package parsing (with optional version)
-        let desired_pkg = match args.next() {
-            Some(arg) => arg,
-            None => return Err("No package was specified"),
-        };
-        let (desired_pkg, desired_pkg_version) = parse_desired_pkg(desired_pkg);
-
         // Default directory in which the packages will be
         // downloaded and installed
         const DEFAULT_SAGE_HOME: &str = ".sage";
@@ -258,14 +1254,82 @@ impl Config {
 
         let download_dir: PathBuf = sage_home.join("downloads");
         let install_dir: PathBuf = sage_home.join("programs");
+        let db_dir: PathBuf = sage_home.join("db");
+        let bin_dir: PathBuf = sage_home.join("bin");
+        let keys_dir: PathBuf = sage_home.join("keys");
+        let repos_file: PathBuf = sage_home.join("repos.yml");
+        let cache_dir: PathBuf = sage_home.join("cache");
+        let logs_dir: PathBuf = sage_home.join("logs");
+        let cache_ttl = match env::var("SAGE_CACHE_TTL") {
+            Ok(secs) => match secs.parse() {
+                Ok(secs) => Duration::from_secs(secs),
+                Err(_) => return Err("SAGE_CACHE_TTL must be a number of seconds"),
+            },
+            Err(_) => Duration::from_secs(DEFAULT_CACHE_TTL),
+        };
 
-        Ok(Config {
+        let mut config = Config {
             download_dir,
             install_dir,
+            db_dir,
+            bin_dir,
+            keys_dir,
+            repos_file,
+            cache_dir,
+            logs_dir,
+            cache_ttl,
             command,
-            desired_pkg,
-            desired_pkg_version,
-        })
+            desired_pkg: None,
+            desired_pkg_version: None,
+            args: Vec::new(),
+            outdated: false,
+            json: false,
+            lts: false,
+            insecure: false,
+            priority: 0,
+            offline: false,
+            verbose: false,
+            variant: Variant::default(),
+        };
+
+        // Options and package parsing (with optional version)
+        while let Some(arg) = args.next() {
+            if arg.starts_with("--") {
+                parse_option(&arg, &mut args, &mut config)?;
+            } else if takes_package(&config.command) && config.desired_pkg.is_none() {
+                let (desired_pkg, desired_pkg_version) = parse_desired_pkg(arg);
+                config.desired_pkg = Some(desired_pkg);
+                config.desired_pkg_version = desired_pkg_version;
+            } else if config.args.len() < max_args(&config.command) {
+                config.args.push(arg);
+            } else {
+                return Err("Too many arguments were specified");
+            }
+        }
+        if requires_package(&config.command) && config.desired_pkg.is_none() {
+            return Err("No package was specified");
+        }
+        if config.command == "search" && config.args.is_empty() {
+            return Err("No search term was specified");
+        }
+        let variant = &config.variant;
+        if variant
+            .with
+            .iter()
+            .any(|option| variant.without.contains(option))
+        {
+            return Err("A build option can't be both enabled and disabled");
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the name of the package the command works on.
+    /// `Config::new` makes sure that there is one for every command that needs it.
+    fn get_desired_pkg(&self) -> &str {
+        self.desired_pkg
+            .as_ref()
+            .expect("The command does not work on a package")
     }
 
     /// Checks if the necessary directory strucutures for `sage`
@@ -280,7 +1344,328 @@ impl Config {
         fs::create_dir_all(&self.download_dir)?;
         // Create the install dir
         fs::create_dir_all(&self.install_dir)?;
+        // Create the installed-package database dir
+        fs::create_dir_all(&self.db_dir)?;
+        // Create the dir that holds the shims
+        fs::create_dir_all(&self.bin_dir)?;
+        // Create the trusted-keys store
+        fs::create_dir_all(&self.keys_dir)?;
+        // Create the repository cache
+        fs::create_dir_all(&self.cache_dir)?;
+        // Create the dir that holds the build logs
+        fs::create_dir_all(&self.logs_dir)?;
 
         Ok(())
     }
 }
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use errors::BuildError;
+
+    fn install_files(install_dir: &Path, files: &[&str]) -> Vec<String> {
+        for file in files {
+            let path = install_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, file).unwrap();
+        }
+        files.iter().map(|file| file.to_string()).collect()
+    }
+
+    fn args(line: &str) -> std::vec::IntoIter<String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        args.into_iter()
+    }
+
+    #[test]
+    fn install_type_is_only_left_out_for_single_executables() {
+        let parser = |file_type: &str| {
+            PackageParser::new(&format!(
+                "name: tool\nversion: 1.0\nurl: https://example.com/tool\ntype: {}",
+                file_type
+            ))
+            .unwrap()
+        };
+
+        assert_eq!(
+            install_type(&parser("binary"), "binary", false).unwrap(),
+            "bin"
+        );
+        assert_eq!(
+            install_type(&parser("unknown"), "unknown", false).unwrap(),
+            "bin"
+        );
+        assert_eq!(
+            install_type(&parser("tar.gz"), "tar.gz", true).unwrap(),
+            "script"
+        );
+        let err = install_type(&parser("tar.gz"), "tar.gz", false).unwrap_err();
+        assert_eq!(err.field, "type");
+        assert!(install_type(&parser("git"), "git", false).is_err());
+    }
+
+    #[test]
+    fn config_parses_package_and_version() {
+        let config = Config::new(args("sage install Python@3.8.0")).unwrap();
+
+        assert_eq!(config.command, "install");
+        assert_eq!(config.get_desired_pkg(), "Python");
+        assert_eq!(config.desired_pkg_version, Some(String::from("3.8.0")));
+    }
+
+    #[test]
+    fn config_parses_list_options() {
+        let config = Config::new(args("sage list --outdated --json")).unwrap();
+
+        assert_eq!(config.command, "list");
+        assert!(config.desired_pkg.is_none());
+        assert!(config.outdated);
+        assert!(config.json);
+    }
+
+    #[test]
+    fn config_parses_optional_package() {
+        let config = Config::new(args("sage upgrade --lts")).unwrap();
+        assert!(config.desired_pkg.is_none());
+        assert!(config.lts);
+
+        let config = Config::new(args("sage upgrade Python")).unwrap();
+        assert_eq!(config.get_desired_pkg(), "Python");
+        assert!(!config.lts);
+    }
+
+    #[test]
+    fn config_parses_extra_arguments() {
+        let config = Config::new(args("sage env fish")).unwrap();
+        assert!(config.desired_pkg.is_none());
+        assert_eq!(config.args, vec!["fish"]);
+
+        assert!(Config::new(args("sage env fish bash")).is_err());
+    }
+
+    #[test]
+    fn config_parses_repo_commands() {
+        let config = Config::new(args(
+            "sage repo add tools https://tools.example.com --priority 10",
+        ))
+        .unwrap();
+        assert!(config.desired_pkg.is_none());
+        assert_eq!(
+            config.args,
+            vec!["add", "tools", "https://tools.example.com"]
+        );
+        assert_eq!(config.priority, 10);
+
+        assert!(Config::new(args("sage repo add tools url --priority high")).is_err());
+        assert!(Config::new(args("sage repo add tools url --priority")).is_err());
+    }
+
+    #[test]
+    fn config_parses_offline_option() {
+        let config = Config::new(args("sage install Python@3.8.0 --offline")).unwrap();
+        assert!(config.offline);
+
+        assert!(Config::new(args("sage update-index")).is_ok());
+        assert!(Config::new(args("sage update-index --offline")).is_err());
+    }
+
+    #[test]
+    fn config_parses_verbose_option() {
+        let config = Config::new(args("sage install Python@3.8.0 --verbose")).unwrap();
+        assert!(config.verbose);
+        assert!(config.logs_dir.ends_with("logs"));
+
+        assert!(Config::new(args("sage list --verbose")).is_err());
+    }
+
+    #[test]
+    fn config_parses_build_options() {
+        let config = Config::new(args(
+            "sage install Python --with optimizations --without ssl --with lto",
+        ))
+        .unwrap();
+        assert_eq!(config.get_desired_pkg(), "Python");
+        assert_eq!(config.variant.with, vec!["optimizations", "lto"]);
+        assert_eq!(config.variant.without, vec!["ssl"]);
+
+        assert!(Config::new(args("sage install Python --with")).is_err());
+        assert!(Config::new(args("sage install Python --with ssl --without ssl")).is_err());
+        assert!(Config::new(args("sage download Python --with ssl")).is_err());
+    }
+
+    #[test]
+    fn config_parses_search_term() {
+        let config = Config::new(args("sage search python --offline")).unwrap();
+        assert_eq!(config.args, vec!["python"]);
+        assert!(config.offline);
+
+        assert!(Config::new(args("sage search")).is_err());
+    }
+
+    #[test]
+    fn requested_versions_are_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let program_dir = dir.path().join("linux/python");
+        fs::create_dir_all(&program_dir).unwrap();
+        fs::write(
+            program_dir.join("metadata.yml"),
+            "versions: [3.9.1, 3.8.6, 3.8.0, 3.7.9]\nlts: 3.8.6\nlatest: 3.9.1\n",
+        )
+        .unwrap();
+        let repo = Repo::new("local", &dir.path().to_string_lossy(), "linux");
+        let resolve = |requested| resolve_version(&repo, "python", requested).unwrap();
+
+        assert_eq!(resolve(None), "3.8.6");
+        assert_eq!(resolve(Some("lts")), "3.8.6");
+        assert_eq!(resolve(Some("latest")), "3.9.1");
+        assert_eq!(resolve(Some("3.8.0")), "3.8.0");
+        assert_eq!(resolve(Some("3.8")), "3.8.6");
+        assert_eq!(resolve(Some("^3.7")), "3.9.1");
+        assert_eq!(resolve(Some(">=3.7,<3.9")), "3.8.6");
+        assert!(resolve_version(&repo, "python", Some("4")).is_err());
+    }
+
+    #[test]
+    fn config_rejects_bad_arguments() {
+        assert!(Config::new(args("sage install")).is_err());
+        assert!(Config::new(args("sage list Python")).is_err());
+        assert!(Config::new(args("sage install Python --outdated")).is_err());
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(2048), "2.0 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 + 512 * 1024), "5.5 MiB");
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(json_string("Python"), "\"Python\"");
+        assert_eq!(json_string("a \"b\"\\c\n"), "\"a \\\"b\\\"\\\\c\\n\"");
+    }
+
+    #[test]
+    fn uninstall_removes_only_owned_files() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path().join("programs");
+        let bin_dir = sage_home.path().join("bin");
+        let db = Database::new(&sage_home.path().join("db"));
+        fs::create_dir_all(sage_home.path().join("db")).unwrap();
+
+        let node_files = install_files(
+            &install_dir,
+            &["node/bin/node", "node/lib/libnode.so", "shared/LICENSE"],
+        );
+        let npm_files = install_files(&install_dir, &["npm/bin/npm", "shared/LICENSE"]);
+        let node = InstalledPackage::new("node", "12.0.0", "Arcanum", "bin", "node", node_files);
+        let npm = InstalledPackage::new("npm", "6.0.0", "Arcanum", "bin", "npm", npm_files);
+        db.add(&node).unwrap();
+        db.add(&npm).unwrap();
+
+        let kept = uninstall(&db, &install_dir, &bin_dir, &node).unwrap();
+
+        assert_eq!(kept, 1);
+        assert!(!install_dir.join("node").exists());
+        assert!(install_dir.join("shared/LICENSE").exists());
+        assert!(install_dir.join("npm/bin/npm").exists());
+        assert!(db.get("node", "12.0.0").unwrap().is_none());
+        assert!(db.get("npm", "6.0.0").unwrap().is_some());
+    }
+
+    #[test]
+    fn uninstall_hands_over_the_active_version() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path().join("programs");
+        let bin_dir = sage_home.path().join("bin");
+        let db = Database::new(&sage_home.path().join("db"));
+        fs::create_dir_all(sage_home.path().join("db")).unwrap();
+
+        for version in &["3.7.0", "3.8.0"] {
+            let bin = format!("python/{}/bin/python3", version);
+            let files = install_files(&install_dir, &[bin.as_str()]);
+            let record = InstalledPackage::new("python", version, "Arcanum", "make", "", files);
+            db.add(&record).unwrap();
+        }
+        activate_version(&install_dir, "python", "3.7.0").unwrap();
+
+        let old = db.get("python", "3.7.0").unwrap().unwrap();
+        uninstall(&db, &install_dir, &bin_dir, &old).unwrap();
+        assert_eq!(active_version(&install_dir, "python").unwrap(), "3.8.0");
+
+        let last = db.get("python", "3.8.0").unwrap().unwrap();
+        uninstall(&db, &install_dir, &bin_dir, &last).unwrap();
+        assert_eq!(active_version(&install_dir, "python"), None);
+        assert!(!install_dir.join("python").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reinstalling_the_active_version_refreshes_its_shims() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path().join("programs");
+        let bin_dir = sage_home.path().join("bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+        let install = |files: &[&str]| {
+            let _ = fs::remove_dir_all(install_dir.join("python").join("3.8.0"));
+            for file in install_files(&install_dir, files) {
+                let executable = fs::Permissions::from_mode(0o755);
+                fs::set_permissions(install_dir.join(file), executable).unwrap();
+            }
+        };
+
+        install(&["python/3.8.0/bin/python3"]);
+        sync_active_version(&install_dir, &bin_dir, "python", "3.8.0").unwrap();
+        assert!(bin_dir.join("python3").exists());
+
+        install(&["python/3.8.0/bin/python3", "python/3.8.0/bin/pip3"]);
+        sync_active_version(&install_dir, &bin_dir, "python", "3.8.0").unwrap();
+        assert!(bin_dir.join("pip3").exists());
+
+        // other versions don't touch the shims of the active one
+        install_files(&install_dir, &["python/3.9.0/bin/idle3"]);
+        sync_active_version(&install_dir, &bin_dir, "python", "3.9.0").unwrap();
+        assert_eq!(active_version(&install_dir, "python").unwrap(), "3.8.0");
+        assert!(!bin_dir.join("idle3").exists());
+    }
+
+    #[test]
+    fn failed_reinstalls_keep_the_previous_version() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path().join("programs");
+        install_files(&install_dir, &["python/3.8.0/bin/python3"]);
+        let install_root = version_dir(&install_dir, "python", "3.8.0");
+
+        let err = replace_version_dir(&install_root, || {
+            fs::create_dir_all(install_root.join("lib"))?;
+            Err(Box::new(BuildError {
+                step: String::from("make"),
+                status: Some(2),
+                log: sage_home.path().join("python_3.8.0.log"),
+                tail: Vec::new(),
+            }))
+        })
+        .err()
+        .unwrap();
+        assert!(err.is::<BuildError>());
+        assert!(install_root.join("bin").join("python3").is_file());
+        assert!(!install_root.join("lib").exists());
+
+        // a successful one replaces it
+        replace_version_dir(&install_root, || {
+            install_files(&install_dir, &["python/3.8.0/bin/pip3"]);
+            Ok(())
+        })
+        .unwrap();
+        assert!(install_root.join("bin").join("pip3").is_file());
+        assert!(!install_root.join("bin").join("python3").exists());
+        assert_eq!(fs::read_dir(install_dir.join("python")).unwrap().count(), 1);
+    }
+}
@@ -19,5 +19,6 @@ fn main() {
 
     if let Err(e) = sage::run(config) {
         eprintln!("Application Error!: {}", e);
+        process::exit(1);
     };
 }
@@ -1,14 +1,23 @@
 extern crate indicatif;
 extern crate reqwest;
+extern crate sha2;
 
 use crate::errors::{
-    DecoderNotFoundError, FileTypeNotSupportedError, InstallTypeNotSupportedError,
-    PathNotFoundError,
+    ChecksumMismatchError, DecoderNotFoundError, FileTypeNotSupportedError,
+    InstallTypeNotSupportedError, MissingChecksumError, MissingInstallInfoError,
+    MissingRevisionError, NotCachedError, PathNotFoundError, RevisionMismatchError,
 };
-use crate::install_utils::{CommandRunner, Decoder, FileTypes, InstallTypes};
+use crate::install_utils::{
+    head_commit, install_binary, BuildLog, BuildOption, CommandRunner, DecoderRegistry, FileTypes,
+    InstallStep, InstallTypes, Variant,
+};
+use crate::repositories::local_path;
+
+use sha2::{Digest, Sha256, Sha512};
 
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::Path;
 
 /// Representation of a valid `sage` package.
@@ -20,6 +29,12 @@ pub struct Package {
     file: String,
     install_type: InstallTypes,
     install_target: String,
+    install_steps: Vec<InstallStep>,
+    build_options: Vec<BuildOption>,
+    sha256: Option<String>,
+    sha512: Option<String>,
+    git_tag: Option<String>,
+    git_commit: Option<String>,
 }
 
 // Public API for Package
@@ -35,18 +50,13 @@ impl Package {
         install_type: &str,
         install_target: &str,
     ) -> Result<Package, Box<dyn Error>> {
-        // Create the FileType instance depending on the given string
-        let file_type_enum = match file_type {
-            "tar.xz" => FileTypes::TarXz,
-            "tar.gz" => FileTypes::TarGz,
-            _ => return Err(Box::new(FileTypeNotSupportedError)),
-        };
+        // Create the FileType instance depending on the given string.
+        // Unknown types are detected once the file has been downloaded
+        let file_type_enum = FileTypes::from_name(file_type);
         // Create the InstallTypes instance depending on the given string
-        // TODO: Add bin install type
-        let install_type_enum = match install_type {
-            "make" => InstallTypes::MakeInstall,
-            "bin" => InstallTypes::Bin,
-            _ => return Err(Box::new(InstallTypeNotSupportedError)),
+        let install_type_enum = match InstallTypes::from_name(install_type) {
+            Some(install_type) => install_type,
+            None => return Err(Box::new(InstallTypeNotSupportedError)),
         };
         Ok(Package {
             name: name.to_string(),
@@ -56,35 +66,107 @@ impl Package {
             file: file_name.to_string(),
             install_type: install_type_enum,
             install_target: install_target.to_string(),
+            install_steps: Vec::new(),
+            build_options: Vec::new(),
+            sha256: None,
+            sha512: None,
+            git_tag: None,
+            git_commit: None,
         })
     }
 
-    /// Downloads the `Package` to the specified download directory.
+    /// Sets the checksums against which the downloaded file will be verified
+    pub fn set_checksums(&mut self, sha256: Option<&str>, sha512: Option<&str>) {
+        self.sha256 = sha256.map(|sum| sum.to_lowercase());
+        self.sha512 = sha512.map(|sum| sum.to_lowercase());
+    }
+
+    /// Sets the revision at which the repository of a git package is cloned.
+    /// The commit, when given, is also what the clone is verified against.
+    pub fn set_git_revision(&mut self, tag: Option<&str>, commit: Option<&str>) {
+        self.git_tag = tag.map(String::from);
+        self.git_commit = commit.map(|commit| commit.to_lowercase());
+    }
+
+    /// Sets the steps that a scripted installation runs, in order
+    pub fn set_install_steps(&mut self, steps: Vec<InstallStep>) {
+        self.install_steps = steps;
+    }
+
+    /// Sets the build options that can be enabled or disabled when building from source
+    pub fn set_build_options(&mut self, options: Vec<BuildOption>) {
+        self.build_options = options;
+    }
+
+    /// Returns the flags that the given variant passes to the build
     ///
     /// # Errors
-    /// Check out the documentation for `reqwest::get`,
-    /// the `copy_to` method of `reqwest::Response`
-    /// and the `create` method of `std::fs::File` to see
-    /// the conditions in which this function could return an error.
-    pub fn download(&self, download_dir: &Path) -> Result<(), Box<dyn Error>> {
+    /// The function will return an error if the variant has an option that the
+    /// package doesn't declare. Check out the documentation for `Variant::flags`.
+    pub fn build_flags(&self, variant: &Variant) -> Result<Vec<String>, Box<dyn Error>> {
+        variant.flags(&self.build_options)
+    }
+
+    /// Downloads the `Package` to the specified download directory,
+    /// verifying its checksums while the file is being written.
+    /// Packages whose url is a *file://* url or a local path are copied instead.
+    /// Packages without checksums are only downloaded if `allow_unverified` is set.
+    /// A file that was already downloaded is reused when it matches the checksums,
+    /// which is the only way to get the package when working `offline`.
+    /// Git packages are cloned into *<download_dir>/<file>* instead,
+    /// and their commit takes the place of the checksums.
+    ///
+    /// # Errors
+    /// The function will return an error if the package has no checksums and
+    /// unverified downloads are not allowed, if the downloaded file doesn't
+    /// match its checksums, in which case the file is deleted, or if the
+    /// file has not been downloaded yet when working offline.
+    /// Check out the documentation for `reqwest::get`
+    /// and the `open` and `create` methods of `std::fs::File` to see
+    /// other conditions in which this function could return an error.
+    pub fn download(
+        &self,
+        download_dir: &Path,
+        allow_unverified: bool,
+        offline: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if let FileTypes::Git = self.file_type {
+            return self.clone_repo(download_dir, allow_unverified, offline);
+        }
+        if self.sha256.is_none() && self.sha512.is_none() && !allow_unverified {
+            return Err(Box::new(MissingChecksumError));
+        }
+        // create a PathBuf instance containing
+        // the full path to the downloaded file
+        let fname = download_dir.join(&self.file);
+        if self.is_downloaded(&fname, offline)? {
+            println!("Using the already downloaded {}.", self.file);
+            return Ok(());
+        }
+        if offline {
+            return Err(Box::new(NotCachedError));
+        }
         // Configure the Progress bar
         let pb = self.start_download_progress();
-        // Specify the target url
-        let target = self.url.as_str();
-        // Make the request
-        let mut response = reqwest::get(target)?;
-
-        // Create the destination file
-        let mut dest_file = {
-            let fname = &self.file;
-            // create a PathBuf instance containing
-            // the full path to the downloaded file
-            let fname = download_dir.join(fname);
-            // Create the dest file
-            File::create(&fname)?
-        };
-        // Copy the file from the response to the destination
-        response.copy_to(&mut dest_file)?;
+        // the file is written under another name, and it only takes
+        // its real one once it has been fully written and verified
+        let part_fname = download_dir.join(format!("{}.part", self.file));
+        let downloaded = self
+            .fetch_into(&part_fname)
+            // Check the digests against the expected checksums
+            .and_then(
+                |(sha256, sha512)| match self.checksum_mismatch(sha256, sha512) {
+                    Some(mismatch) => Err(mismatch.into()),
+                    None => Ok(fs::rename(&part_fname, &fname)?),
+                },
+            );
+        if let Err(e) = downloaded {
+            pb.finish_and_clear();
+            if part_fname.is_file() {
+                fs::remove_file(&part_fname)?;
+            }
+            return Err(e);
+        }
 
         // Signal the progress bar to end
         self.finish_download_progress(pb);
@@ -92,65 +174,273 @@ impl Package {
         Ok(())
     }
 
-    /// Install the 'Package' in the system.
-    pub fn install<D: Decoder>(
+    /// Detects the type of the downloaded file from its magic bytes,
+    /// which takes precedence over the declared one since that one
+    /// might be missing or wrong. Files whose type is still unknown
+    /// are decoded by the decoder registered for their extension.
+    ///
+    /// # Errors
+    /// Check out the documentation for `FileTypes::detect`.
+    pub fn detect_file_type(&mut self, download_dir: &Path) -> Result<(), Box<dyn Error>> {
+        // a cloned repository is a directory, not a file
+        if let FileTypes::Git = self.file_type {
+            return Ok(());
+        }
+        let detected = FileTypes::detect(&download_dir.join(&self.file))?;
+        if let FileTypes::Unknown = detected {
+            return Ok(());
+        }
+        self.file_type = detected;
+
+        Ok(())
+    }
+
+    /// Install the 'Package' in the system, inside of the given install directory.
+    /// Archives are unpacked by the decoder registered for their type, or for
+    /// the extension of the file when the type is unknown.
+    /// Single executables skip the decoding and are installed as *bin/<target>*,
+    /// or *bin/<name>* when the package has no install target.
+    /// Everything printed while building from source goes to the build log,
+    /// and the build flags are passed to make, cmake, meson or cargo.
+    ///
+    /// # Errors
+    /// The function will return an error if there is no decoder for the file,
+    /// if no install directory was given, or if a step of the build fails.
+    /// Check out the documentation for `Decoder::decode`, `install_binary`
+    /// and `CommandRunner::run` to find out other reasons for this function to fail.
+    pub fn install(
         &self,
         download_dir: &Path,
-        sage_home_path: Option<&str>,
-        decoder: Option<D>,
+        install_dir: Option<&Path>,
+        decoders: &DecoderRegistry,
+        build_log: &BuildLog,
+        build_flags: &[String],
     ) -> Result<(), Box<dyn Error>> {
-        // Start the install progress bar
-        let pb = self.start_install_progress();
+        // Start the install progress bar, unless the build output is shown instead
+        let verbose = build_log.is_verbose();
+        let pb = self.start_install_progress(verbose);
         // analyze the type of the DOWNLOADED file
         match self.file_type {
-            FileTypes::TarGz | FileTypes::TarXz => {
-                // In this case we want to DECODE the tar archives so...
-                // Check if a valid decoder has been passed as a parameter
-                if let None = decoder {
-                    return Err(Box::new(DecoderNotFoundError));
+            FileTypes::Raw => {
+                // there is nothing to decode or build, the file itself is the program
+                let install_dir = match install_dir {
+                    Some(install_dir) => install_dir,
+                    None => return Err(Box::new(PathNotFoundError)),
+                };
+                if !matches!(self.install_type, InstallTypes::Bin) {
+                    return Err(Box::new(InstallTypeNotSupportedError));
+                }
+                let binary_name = if self.install_target.is_empty() {
+                    &self.name
                 } else {
-                    // decode the file
-                    decoder.unwrap().decode(
+                    &self.install_target
+                };
+                install_binary(&download_dir.join(&self.file), install_dir, binary_name)?;
+                self.finish_install_progress(pb, verbose);
+                return Ok(());
+            }
+            // the sources were already cloned, there is nothing to decode
+            FileTypes::Git => (),
+            _ => {
+                // unpacking an archive right into the download directory means
+                // that there must be a target inside of it to install from
+                if self.install_target.is_empty() {
+                    return Err(Box::new(MissingInstallInfoError {
+                        field: String::from("target"),
+                    }));
+                }
+                // In this case we want to DECODE the archives so...
+                let decoder = decoders
+                    .get(self.file_type.name())
+                    .or_else(|| decoders.for_file(&self.file));
+                match decoder {
+                    Some(decoder) => decoder.decode(
                         download_dir.join(&self.file).to_str().unwrap(),
                         download_dir,
-                    )?;
+                    )?,
+                    None => match self.file_type {
+                        FileTypes::Unknown => return Err(Box::new(FileTypeNotSupportedError)),
+                        _ => return Err(Box::new(DecoderNotFoundError)),
+                    },
                 }
-            } // TODO: Other file types...
+            }
         }
+        // the directory in which the install procedure is applied,
+        // which is relative to the root of the repository for git packages
+        let source_dir = match self.file_type {
+            FileTypes::Git => download_dir.join(&self.file).join(&self.install_target),
+            _ => download_dir.join(&self.install_target),
+        };
+        let install_dir = match install_dir {
+            Some(install_dir) => install_dir,
+            None => return Err(Box::new(PathNotFoundError)),
+        };
         // analyze the type of INSTALLATION process
-        match self.install_type {
+        let install_cmd = match self.install_type {
+            // build it from source, with the install dir as its prefix
             InstallTypes::MakeInstall => {
-                // Run the 'Make' command using the CommandRunner
-                if let None = sage_home_path {
-                    return Err(Box::new(PathNotFoundError));
-                } else {
-                    let make_cmd =
-                        CommandRunner::Make(&self.install_target, sage_home_path.unwrap());
-                    make_cmd.run()?
-                }
+                CommandRunner::Make(&source_dir, install_dir, build_log, build_flags)
             }
-            InstallTypes::Bin => {
-                // copy everything in the target directory to sage_home_path
-                if let None = sage_home_path {
-                    return Err(Box::new(PathNotFoundError));
-                } else {
-                    let bin_cmd = CommandRunner::Bin(&self.install_target, sage_home_path.unwrap());
-                    bin_cmd.run()?
-                }
+            InstallTypes::CMake => {
+                CommandRunner::CMake(&source_dir, install_dir, build_log, build_flags)
             }
-        }
+            InstallTypes::Meson => {
+                CommandRunner::Meson(&source_dir, install_dir, build_log, build_flags)
+            }
+            InstallTypes::Cargo => {
+                CommandRunner::Cargo(&source_dir, install_dir, build_log, build_flags)
+            }
+            // run the steps listed by the package
+            InstallTypes::Script => CommandRunner::Script(
+                &source_dir,
+                install_dir,
+                build_log,
+                &self.install_steps,
+                &self.version,
+            ),
+            // copy everything in the target directory to the install_dir
+            InstallTypes::Bin => CommandRunner::Bin(&source_dir, install_dir),
+        };
+        install_cmd.run()?;
         // end the progress bar
-        self.finish_install_progress(pb);
+        self.finish_install_progress(pb, verbose);
         Ok(())
     }
 
-    pub fn get_file_type(&self) -> &FileTypes {
-        return &self.file_type;
+    /// Returns the version of the `Package`
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+
+    /// Returns the installation procedure of the `Package`
+    pub fn get_install_type(&self) -> &InstallTypes {
+        &self.install_type
+    }
+
+    /// Returns the directory in which the install procedure will be applied
+    pub fn get_install_target(&self) -> &str {
+        &self.install_target
+    }
+
+    /// Returns the build options declared by the `Package`
+    pub fn get_build_options(&self) -> &[BuildOption] {
+        &self.build_options
     }
 }
 
 // Private API for Package
 impl Package {
+    /// Clones the repository of a git package at its pinned tag or commit,
+    /// reusing a previous clone that is already at the pinned commit.
+    ///
+    /// # Errors
+    /// The function will return an error if the package pins no revision, if it
+    /// pins no commit and unverified downloads are not allowed, if the clone
+    /// is not at the pinned commit, in which case it is deleted, or if the
+    /// repository has not been cloned yet when working offline.
+    /// Check out the documentation for `CommandRunner::run` and `head_commit`
+    /// to find out other reasons for this function to fail.
+    fn clone_repo(
+        &self,
+        download_dir: &Path,
+        allow_unverified: bool,
+        offline: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.git_commit.is_none() && !allow_unverified {
+            return Err(Box::new(MissingChecksumError));
+        }
+        // a tag is checked out as it is, and then verified against the commit
+        let revision = match self.git_tag.as_ref().or(self.git_commit.as_ref()) {
+            Some(revision) => revision,
+            None => return Err(Box::new(MissingRevisionError)),
+        };
+        let clone_dir = download_dir.join(&self.file);
+        if let Ok(head) = head_commit(&clone_dir) {
+            let reusable = match &self.git_commit {
+                Some(commit) => *commit == head,
+                None => offline,
+            };
+            if reusable {
+                println!("Using the already downloaded {}.", self.file);
+                return Ok(());
+            }
+        }
+        if offline {
+            return Err(Box::new(NotCachedError));
+        }
+
+        let pb = self.start_download_progress();
+        CommandRunner::Git(&self.url, &clone_dir, revision).run()?;
+        if let Some(commit) = &self.git_commit {
+            let head = head_commit(&clone_dir)?;
+            if *commit != head {
+                pb.finish_and_clear();
+                fs::remove_dir_all(&clone_dir)?;
+                return Err(Box::new(RevisionMismatchError {
+                    expected: commit.clone(),
+                    found: head,
+                }));
+            }
+        }
+        self.finish_download_progress(pb);
+
+        Ok(())
+    }
+
+    /// Checks if the file has already been downloaded and matches the checksums.
+    /// Files of packages without checksums are only trusted when `trust_unverified` is set.
+    fn is_downloaded(&self, fname: &Path, trust_unverified: bool) -> Result<bool, Box<dyn Error>> {
+        if !fname.is_file() {
+            return Ok(false);
+        }
+        if self.sha256.is_none() && self.sha512.is_none() {
+            return Ok(trust_unverified);
+        }
+        let (sha256, sha512) = copy_with_digests(&mut File::open(fname)?, &mut io::sink())?;
+
+        Ok(self.checksum_mismatch(sha256, sha512).is_none())
+    }
+
+    /// Writes the file of the package to the given path, returning
+    /// its SHA-256 and SHA-512 digests
+    ///
+    /// # Errors
+    /// Check out the documentation for `reqwest::get`, `local_path`, `copy_with_digests`
+    /// and the `open` and `create` methods of `std::fs::File`.
+    fn fetch_into(&self, path: &Path) -> Result<(String, String), Box<dyn Error>> {
+        // Make the request, or open the file if it is a local one
+        let mut response: Box<dyn Read> = match local_path(&self.url)? {
+            Some(source) => Box::new(File::open(source)?),
+            None => Box::new(reqwest::get(self.url.as_str())?),
+        };
+        // Copy the file from the response to the destination
+        let mut dest_file = File::create(path)?;
+
+        Ok(copy_with_digests(&mut response, &mut dest_file)?)
+    }
+
+    /// Compares the digests of a file against the expected checksums,
+    /// returning the first one that doesn't match
+    fn checksum_mismatch(&self, sha256: String, sha512: String) -> Option<ChecksumMismatchError> {
+        let checks = [
+            ("sha256", &self.sha256, sha256),
+            ("sha512", &self.sha512, sha512),
+        ];
+        for (algorithm, expected, found) in checks.iter() {
+            if let Some(expected) = expected {
+                if expected != found {
+                    return Some(ChecksumMismatchError {
+                        algorithm,
+                        expected: expected.clone(),
+                        found: found.clone(),
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
     fn start_download_progress(&self) -> indicatif::ProgressBar {
         let pb = indicatif::ProgressBar::new_spinner();
         pb.enable_steady_tick(120);
@@ -178,7 +468,15 @@ impl Package {
         ));
     }
 
-    fn start_install_progress(&self) -> indicatif::ProgressBar {
+    fn start_install_progress(&self, hidden: bool) -> indicatif::ProgressBar {
+        let message = format!(
+            "Installing {}@{}. This may take a while...",
+            self.name, self.version
+        );
+        if hidden {
+            println!("{}", message);
+            return indicatif::ProgressBar::hidden();
+        }
         let pb = indicatif::ProgressBar::new_spinner();
         pb.enable_steady_tick(120);
         pb.set_style(
@@ -194,25 +492,274 @@ impl Package {
                 ])
                 .template("{spinner:.green} {msg}"),
         );
-        pb.set_message(&format!(
-            "Installing {}@{}. This may take a while...",
-            self.name, self.version
-        ));
+        pb.set_message(&message);
         pb
     }
 
-    fn finish_install_progress(&self, pb: indicatif::ProgressBar) {
-        pb.finish_with_message(&format!(
+    fn finish_install_progress(&self, pb: indicatif::ProgressBar, hidden: bool) {
+        let message = format!(
             "Done! Package {}@{} successfully installed in your system.",
             self.name, self.version
-        ));
+        );
+        if hidden {
+            println!("{}", message);
+        }
+        pb.finish_with_message(&message);
     }
 }
 
+/// Copies everything from the reader to the writer, returning the
+/// hex-encoded sha256 and sha512 digests of the copied data
+fn copy_with_digests<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> io::Result<(String, String)> {
+    let mut sha256 = Sha256::new();
+    let mut sha512 = Sha512::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        sha256.input(&buf[..read]);
+        sha512.input(&buf[..read]);
+        writer.write_all(&buf[..read])?;
+    }
+    writer.flush()?;
+
+    Ok((
+        format!("{:x}", sha256.result()),
+        format!("{:x}", sha512.result()),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn digests_are_computed_while_copying() {
+        let mut copy = Vec::new();
+        let (sha256, sha512) = copy_with_digests(&mut "abc".as_bytes(), &mut copy).unwrap();
+
+        assert_eq!(copy, b"abc");
+        assert_eq!(
+            sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+    }
+
+    #[test]
+    fn local_packages_are_copied() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("hello-1.0.tar.gz");
+        fs::write(&source, "abc").unwrap();
+        let download_dir = dir.path().join("downloads");
+        fs::create_dir(&download_dir).unwrap();
+
+        let mut pkg = Package::new(
+            "hello",
+            "1.0",
+            &format!("file://{}", source.display()),
+            "tar.gz",
+            "hello-1.0.tar.gz",
+            "bin",
+            "hello-1.0",
+        )
+        .unwrap();
+        pkg.set_checksums(
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            None,
+        );
+        pkg.download(&download_dir, false, false).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(download_dir.join("hello-1.0.tar.gz")).unwrap(),
+            "abc"
+        );
+
+        // the copy is reused offline, even without the original file
+        fs::remove_file(&source).unwrap();
+        pkg.download(&download_dir, false, true).unwrap();
+        fs::write(download_dir.join("hello-1.0.tar.gz"), "abd").unwrap();
+        let err = pkg.download(&download_dir, false, true).err().unwrap();
+        assert!(err.is::<NotCachedError>());
+
+        // failed downloads leave nothing behind, not even a partial file
+        let downloaded = || fs::read_dir(&download_dir).unwrap().count();
+        fs::remove_file(download_dir.join("hello-1.0.tar.gz")).unwrap();
+        fs::write(&source, "abd").unwrap();
+        let err = pkg.download(&download_dir, false, false).err().unwrap();
+        assert!(err.is::<ChecksumMismatchError>());
+        assert_eq!(downloaded(), 0);
+        fs::remove_file(&source).unwrap();
+        assert!(pkg.download(&download_dir, false, false).is_err());
+        assert_eq!(downloaded(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raw_binaries_are_installed_as_they_are() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("jq-linux64"), "\x7FELF").unwrap();
+        let install_dir = dir.path().join("jq").join("1.6");
+        let log = BuildLog::new(&dir.path().join("jq_1.6.log"), false);
+
+        let pkg = Package::new(
+            "jq",
+            "1.6",
+            "https://example.com/jq-linux64",
+            "binary",
+            "jq-linux64",
+            "bin",
+            "",
+        )
+        .unwrap();
+        pkg.install(
+            dir.path(),
+            Some(&install_dir),
+            &DecoderRegistry::new(),
+            &log,
+            &[],
+        )
+        .unwrap();
+
+        let binary = install_dir.join("bin").join("jq");
+        assert_eq!(fs::read_to_string(&binary).unwrap(), "\x7FELF");
+        let mode = fs::metadata(&binary).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        let renamed = Package::new(
+            "jq",
+            "1.6",
+            "https://example.com/jq-linux64",
+            "raw",
+            "jq-linux64",
+            "bin",
+            "../jq",
+        )
+        .unwrap();
+        assert!(renamed
+            .install(
+                dir.path(),
+                Some(&install_dir),
+                &DecoderRegistry::new(),
+                &log,
+                &[]
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn archives_without_an_install_target_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("unrelated.tar.gz"), "").unwrap();
+        let install_dir = dir.path().join("tool").join("1.0");
+        let log = BuildLog::new(&dir.path().join("tool_1.0.log"), false);
+
+        let pkg = Package::new(
+            "tool",
+            "1.0",
+            "https://example.com/tool-1.0.tar.gz",
+            "tar.gz",
+            "tool-1.0.tar.gz",
+            "bin",
+            "",
+        )
+        .unwrap();
+        let err = pkg
+            .install(
+                dir.path(),
+                Some(&install_dir),
+                &DecoderRegistry::default(),
+                &log,
+                &[],
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<MissingInstallInfoError>().unwrap().field,
+            "target"
+        );
+        // nothing from the download directory was installed
+        assert!(!install_dir.exists());
+    }
+
+    #[test]
+    fn git_packages_are_cloned_at_their_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let work = dir.path().join("work");
+        fs::create_dir(&work).unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(&work)
+                .args(["-c", "user.name=sage", "-c", "user.email=sage@example.com"])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        git(&["init", "--quiet"]);
+        fs::write(work.join("VERSION"), "1.0").unwrap();
+        git(&["add", "VERSION"]);
+        git(&["commit", "--quiet", "-m", "Release 1.0"]);
+        git(&["tag", "v1.0"]);
+        let commit = git(&["rev-parse", "HEAD"]);
+        fs::write(work.join("VERSION"), "2.0").unwrap();
+        git(&["commit", "--quiet", "-am", "Start 2.0"]);
+        // packages point at a bare repository, like a real remote
+        let bare = dir.path().join("tool.git");
+        git(&["clone", "--quiet", "--bare", ".", bare.to_str().unwrap()]);
+
+        let download_dir = dir.path().join("downloads");
+        fs::create_dir(&download_dir).unwrap();
+        let mut pkg = Package::new(
+            "tool",
+            "1.0",
+            bare.to_str().unwrap(),
+            "git",
+            "tool-1.0",
+            "bin",
+            "",
+        )
+        .unwrap();
+        pkg.set_git_revision(Some("v1.0"), None);
+        let err = pkg.download(&download_dir, false, false).err().unwrap();
+        assert!(err.is::<MissingChecksumError>());
+
+        pkg.set_git_revision(Some("v1.0"), Some(&commit.to_uppercase()));
+        pkg.download(&download_dir, false, false).unwrap();
+        let clone_dir = download_dir.join("tool-1.0");
+        assert_eq!(
+            fs::read_to_string(clone_dir.join("VERSION")).unwrap(),
+            "1.0"
+        );
+        pkg.detect_file_type(&download_dir).unwrap();
+        // the clone is reused offline
+        pkg.download(&download_dir, false, true).unwrap();
+
+        pkg.set_git_revision(Some("v1.0"), Some(&"0".repeat(40)));
+        let err = pkg.download(&download_dir, false, false).err().unwrap();
+        assert!(err.is::<RevisionMismatchError>());
+        assert!(!clone_dir.exists());
+
+        pkg.set_git_revision(None, None);
+        let err = pkg.download(&download_dir, true, false).err().unwrap();
+        assert!(err.is::<MissingRevisionError>());
+    }
+
     #[test]
     fn package_created_correctly() {
         let pkg = Package::new(
@@ -1,12 +1,45 @@
+extern crate minisign_verify;
 extern crate reqwest;
+extern crate yaml_rust;
+
+use crate::cache::Cache;
+use crate::errors::{
+    CorruptedRepoConfigError, InvalidPackageNameError, InvalidRepoNameError, NotCachedError,
+    PackageNotFoundError, RepoAlreadyExistsError, RepoNotFoundError, SignatureVerificationError,
+    UnsupportedSchemeError,
+};
+use crate::install_utils::is_plain_name;
+
+use minisign_verify::{PublicKey, Signature};
+use yaml_rust::yaml::{Array, Hash};
+use yaml_rust::{Yaml, YamlEmitter, YamlLoader};
 
 use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Name of the repository that sage uses when none has been configured
+pub const DEFAULT_REPO_NAME: &str = "Arcanum";
+/// Base url of the repository that sage uses when none has been configured
+pub const DEFAULT_REPO_URL: &str = "https://raw.githubusercontent.com/rvillegasm/Arcanum/master/";
+/// Suffix of the detached signatures of the files of a repository
+const SIGNATURE_SUFFIX: &str = ".minisig";
 
 /// A valid repository representation for **sage**
 pub struct Repo {
     name: String,
     base_url: String,
     host_os: String,
+    priority: i64,
+    trusted_keys: Vec<PublicKey>,
+    cache: Option<Cache>,
+}
+
+/// Every repository configured by the user, which are searched
+/// from the highest priority to the lowest one
+pub struct RepoList {
+    repos: Vec<Repo>,
 }
 
 impl Repo {
@@ -16,6 +49,9 @@ impl Repo {
             name: String::from(name),
             base_url: String::from(base_url),
             host_os: String::from(host_os),
+            priority: 0,
+            trusted_keys: Vec::new(),
+            cache: None,
         }
     }
 
@@ -34,55 +70,703 @@ impl Repo {
         &self.host_os
     }
 
+    /// Returns the priority of the `Repo`. Repos with higher priorities are searched first.
+    pub fn get_priority(&self) -> i64 {
+        self.priority
+    }
+
+    /// Sets the priority of the `Repo`
+    pub fn set_priority(&mut self, priority: i64) {
+        self.priority = priority;
+    }
+
+    /// Sets the public keys that the files of the `Repo` must be signed with.
+    /// When a repo has trusted keys, every file fetched from it has to come
+    /// with a valid detached minisign signature (*<file>.minisig*).
+    pub fn set_trusted_keys(&mut self, keys: Vec<PublicKey>) {
+        self.trusted_keys = keys;
+    }
+
+    /// Sets the cache in which the files fetched from the `Repo` are kept.
+    /// Local repos are always read directly, so they don't use it.
+    pub fn set_cache(&mut self, cache: Cache) {
+        self.cache = Some(cache);
+    }
+
     /// Returns a Yaml-formatted string holding the metadata of a program.
+    /// The base url of the repo can be an http(s) url, a *file://* url or a local path.
     ///
     /// # Errors
-    /// Check out the documentation for reqwest::get the see the conditions in which
-    /// this method could return an error.
-    /// It also fails when the repo is not valid.
+    /// Check out the documentation for reqwest::get and std::fs::read_to_string
+    /// the see the conditions in which this method could return an error.
+    /// It also fails when the signature of the file can't be verified,
+    /// or when working offline and the file is not cached.
     pub fn get_program_metadata(&self, program_name: &str) -> Result<String, Box<dyn Error>> {
-        let target_url = match self.get_name() {
-            "Arcanum" => format!(
-                "{}/{}/{}/metadata.yml",
-                self.get_base_url(),
-                self.get_host_os(),
-                program_name
-            ),
-
-            _ => panic!("The specified repository is not supported!"),
-        };
-
-        let mut response = reqwest::get(&target_url)?;
+        // the name is part of the path of the file, in the repo and in the cache
+        if !is_plain_name(program_name) {
+            return Err(Box::new(InvalidPackageNameError));
+        }
+        let key = format!("{}/{}/metadata.yml", self.get_host_os(), program_name);
 
-        Ok(response.text()?)
+        self.fetch_file(&key)
     }
 
     /// Returns a Yaml-formatted string holding the package info of a program.
+    /// The base url of the repo can be an http(s) url, a *file://* url or a local path.
     ///
     /// # Errors
-    /// Check out the documentation for reqwest::get the see the conditions in which
-    /// this method could return an error.
-    /// It also fails when the repo is not valid.
+    /// Check out the documentation for reqwest::get and std::fs::read_to_string
+    /// the see the conditions in which this method could return an error.
+    /// It also fails when the signature of the file can't be verified,
+    /// or when working offline and the file is not cached.
     pub fn get_program_package(
         &self,
         program_name: &str,
         program_version: &str,
     ) -> Result<String, Box<dyn Error>> {
-        let target_url = match self.get_name() {
-            "Arcanum" => format!(
-                "{}/{}/{}/{}_{}.yml",
-                self.get_base_url(),
-                self.get_host_os(),
-                program_name,
-                program_name,
-                program_version
-            ),
-
-            _ => panic!("The specified repository is not supported!"),
+        if !is_plain_name(program_name) || !is_plain_name(program_version) {
+            return Err(Box::new(InvalidPackageNameError));
+        }
+        let key = format!(
+            "{}/{}/{}_{}.yml",
+            self.get_host_os(),
+            program_name,
+            program_name,
+            program_version
+        );
+
+        self.fetch_file(&key)
+    }
+
+    /// Returns a Yaml-formatted string holding the index of every package in the `Repo`.
+    ///
+    /// # Errors
+    /// Check out the documentation for `Repo::get_program_metadata`.
+    pub fn get_index(&self) -> Result<String, Box<dyn Error>> {
+        let key = format!("{}/index.yml", self.get_host_os());
+
+        self.fetch_file(&key)
+    }
+
+    /// Fetches again every cached file of the `Repo`, dropping the ones
+    /// that are no longer available. Returns the amount of refreshed files.
+    ///
+    /// # Errors
+    /// The function will return an error if a file can't be fetched
+    /// for any other reason than not being available anymore.
+    pub fn refresh(&self) -> Result<usize, Box<dyn Error>> {
+        let cache = match self.remote_cache() {
+            Some(cache) => cache,
+            None => return Ok(0),
         };
+        let mut refreshed = 0;
+        for key in cache.keys()? {
+            // signatures are refreshed along with their files
+            if key.ends_with(SIGNATURE_SUFFIX) {
+                continue;
+            }
+            match self.fetch_verified(&key) {
+                Ok((contents, signature)) => {
+                    self.cache_file(cache, &key, &contents, signature.as_deref())?;
+                    refreshed += 1;
+                }
+                Err(e) if e.is::<PackageNotFoundError>() => {
+                    cache.remove(&key)?;
+                    cache.remove(&format!("{}{}", key, SIGNATURE_SUFFIX))?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(refreshed)
+    }
+
+    /// Returns the url of a file of the repo
+    fn url_of(&self, key: &str) -> String {
+        format!("{}/{}", self.get_base_url().trim_end_matches('/'), key)
+    }
+
+    /// Returns the cache of the repo, unless it is a local one
+    fn remote_cache(&self) -> Option<&Cache> {
+        match local_path(&self.base_url) {
+            Ok(None) => self.cache.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Fetches a file of the repo, serving it from the cache while it is fresh.
+    /// Cached files are checked against the current trusted keys every time,
+    /// so the ones cached before a key was trusted are fetched again.
+    fn fetch_file(&self, key: &str) -> Result<String, Box<dyn Error>> {
+        let cache = match self.remote_cache() {
+            Some(cache) => cache,
+            None => return Ok(self.fetch_verified(key)?.0),
+        };
+        if let Some(contents) = cache.get(key) {
+            match self.verify_cached(cache, key, &contents) {
+                Ok(()) => return Ok(contents),
+                Err(e) if cache.is_offline() => return Err(e),
+                Err(_) => (),
+            }
+        } else if cache.is_offline() {
+            return Err(Box::new(NotCachedError));
+        }
+        let (contents, signature) = self.fetch_verified(key)?;
+        self.cache_file(cache, key, &contents, signature.as_deref())?;
+
+        Ok(contents)
+    }
+
+    /// Checks a cached file against the signature cached along with it,
+    /// when the repo has trusted keys
+    fn verify_cached(
+        &self,
+        cache: &Cache,
+        key: &str,
+        contents: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.trusted_keys.is_empty() {
+            return Ok(());
+        }
+        match cache.get(&format!("{}{}", key, SIGNATURE_SUFFIX)) {
+            Some(signature) => verify_signature(contents, &signature, key, &self.trusted_keys),
+            None => Err(Box::new(SignatureVerificationError)),
+        }
+    }
+
+    /// Stores a verified file in the cache, along with its signature if it has one
+    fn cache_file(
+        &self,
+        cache: &Cache,
+        key: &str,
+        contents: &str,
+        signature: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let signature_key = format!("{}{}", key, SIGNATURE_SUFFIX);
+        match signature {
+            Some(signature) => cache.put(&signature_key, signature)?,
+            None => cache.remove(&signature_key)?,
+        }
+        cache.put(key, contents)
+    }
+
+    /// Fetches a file of the repo, checking its signature when the repo has trusted keys.
+    /// Returns the contents of the file along with its signature, if it was checked.
+    fn fetch_verified(&self, key: &str) -> Result<(String, Option<String>), Box<dyn Error>> {
+        let target_url = self.url_of(key);
+        let contents = fetch(&target_url)?;
+        if self.trusted_keys.is_empty() {
+            return Ok((contents, None));
+        }
+        let signature = match fetch(&format!("{}{}", target_url, SIGNATURE_SUFFIX)) {
+            Ok(signature) => signature,
+            Err(_) => return Err(Box::new(SignatureVerificationError)),
+        };
+        verify_signature(&contents, &signature, key, &self.trusted_keys)?;
+
+        Ok((contents, Some(signature)))
+    }
+}
+
+impl RepoList {
+    /// Reads the repositories configured in the given yaml file, loading
+    /// the trusted keys of each one from its own directory inside of `keys_dir`.
+    /// When the file doesn't exist, only the default repository (Arcanum) is used.
+    ///
+    /// # Errors
+    /// The function will return an error if the file is not valid,
+    /// or if the trusted keys of a repository can't be read.
+    pub fn load(path: &Path, host_os: &str, keys_dir: &Path) -> Result<RepoList, Box<dyn Error>> {
+        let mut list = RepoList { repos: Vec::new() };
+        if !path.is_file() {
+            list.add(DEFAULT_REPO_NAME, DEFAULT_REPO_URL, 0, host_os)?;
+        } else {
+            let docs = YamlLoader::load_from_str(&fs::read_to_string(path)?)?;
+            let entries = match docs.first().map(|doc| &doc["repositories"]) {
+                Some(Yaml::Array(entries)) => entries.clone(),
+                Some(Yaml::BadValue) | None => Vec::new(),
+                Some(_) => return Err(Box::new(CorruptedRepoConfigError)),
+            };
+            for entry in entries {
+                let (name, url) = match (entry["name"].as_str(), entry["url"].as_str()) {
+                    (Some(name), Some(url)) => (name, url),
+                    _ => return Err(Box::new(CorruptedRepoConfigError)),
+                };
+                let priority = entry["priority"].as_i64().unwrap_or(0);
+                list.add(name, url, priority, host_os)?;
+            }
+        }
+
+        for repo in list.repos.iter_mut() {
+            let keys = read_trusted_keys(&keys_dir.join(&repo.name))?;
+            repo.set_trusted_keys(keys);
+        }
+
+        Ok(list)
+    }
+
+    /// Writes the repositories to the given yaml file
+    ///
+    /// # Errors
+    /// Check out the documentation for `std::fs::write`.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let entries: Array = self
+            .repos
+            .iter()
+            .map(|repo| {
+                let mut entry = Hash::new();
+                entry.insert(
+                    Yaml::String(String::from("name")),
+                    Yaml::String(repo.name.clone()),
+                );
+                entry.insert(
+                    Yaml::String(String::from("url")),
+                    Yaml::String(repo.base_url.clone()),
+                );
+                entry.insert(
+                    Yaml::String(String::from("priority")),
+                    Yaml::Integer(repo.priority),
+                );
+                Yaml::Hash(entry)
+            })
+            .collect();
+        let mut doc = Hash::new();
+        doc.insert(
+            Yaml::String(String::from("repositories")),
+            Yaml::Array(entries),
+        );
+
+        let mut out = String::new();
+        YamlEmitter::new(&mut out).dump(&Yaml::Hash(doc))?;
+        out.push('\n');
+        fs::write(path, out)?;
+
+        Ok(())
+    }
+
+    /// Adds a new repository to the list. The name of the repository names
+    /// its directories in the keys store and the cache, so it can't be a path.
+    ///
+    /// # Errors
+    /// The function will return an error if the name is not a plain directory name,
+    /// if there already is a repository with that name, or if its url has a scheme
+    /// that is not supported.
+    pub fn add(
+        &mut self,
+        name: &str,
+        base_url: &str,
+        priority: i64,
+        host_os: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if !is_plain_name(name) {
+            return Err(Box::new(InvalidRepoNameError));
+        }
+        if self.get(name).is_some() {
+            return Err(Box::new(RepoAlreadyExistsError));
+        }
+        local_path(base_url)?;
+        let mut repo = Repo::new(name, base_url, host_os);
+        repo.set_priority(priority);
+        // keep the list sorted by priority, leaving ties in the order they were added
+        let index = self
+            .repos
+            .iter()
+            .position(|other| other.priority < priority)
+            .unwrap_or(self.repos.len());
+        self.repos.insert(index, repo);
+
+        Ok(())
+    }
+
+    /// Removes a repository from the list
+    ///
+    /// # Errors
+    /// The function will return an error if there is no repository with that name.
+    pub fn remove(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        match self.repos.iter().position(|repo| repo.name == name) {
+            Some(index) => {
+                self.repos.remove(index);
+                Ok(())
+            }
+            None => Err(Box::new(RepoNotFoundError)),
+        }
+    }
+
+    /// Makes every repository keep the files fetched from it in its own
+    /// directory inside of `cache_dir`, for as long as the time to live
+    pub fn set_cache(&mut self, cache_dir: &Path, ttl: Duration, offline: bool) {
+        for repo in self.repos.iter_mut() {
+            let cache = Cache::new(&cache_dir.join(&repo.name), ttl, offline);
+            repo.set_cache(cache);
+        }
+    }
+
+    /// Fetches again every cached file of every repository,
+    /// returning the amount of refreshed files
+    ///
+    /// # Errors
+    /// Check out the documentation for `Repo::refresh`.
+    pub fn refresh(&self) -> Result<usize, Box<dyn Error>> {
+        let mut refreshed = 0;
+        for repo in &self.repos {
+            refreshed += repo.refresh()?;
+        }
+
+        Ok(refreshed)
+    }
+
+    /// Returns the repository with the given name, if any
+    pub fn get(&self, name: &str) -> Option<&Repo> {
+        self.repos.iter().find(|repo| repo.name == name)
+    }
+
+    /// Returns every repository, from the highest priority to the lowest one
+    pub fn iter(&self) -> std::slice::Iter<'_, Repo> {
+        self.repos.iter()
+    }
+
+    /// Returns the repository with the highest priority that provides the program
+    ///
+    /// # Errors
+    /// The function will return an error if no repository provides the program,
+    /// or if a repository fails for any other reason than not having it.
+    /// When working offline and no repository has the program cached,
+    /// the error says so.
+    pub fn find(&self, program_name: &str) -> Result<&Repo, Box<dyn Error>> {
+        let mut not_cached = false;
+        for repo in &self.repos {
+            match repo.get_program_metadata(program_name) {
+                Ok(_) => return Ok(repo),
+                Err(e) if e.is::<PackageNotFoundError>() => continue,
+                Err(e) if e.is::<NotCachedError>() => not_cached = true,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if not_cached {
+            Err(Box::new(NotCachedError))
+        } else {
+            Err(Box::new(PackageNotFoundError))
+        }
+    }
+}
+
+/// Returns the local path the location points to, if it is a *file://* url
+/// or a plain path, and `None` when it is an http(s) url
+///
+/// # Errors
+/// The function will return an error if the location has any other scheme,
+/// like *ftp://* or a mistyped *htps://*.
+pub fn local_path(location: &str) -> Result<Option<PathBuf>, UnsupportedSchemeError> {
+    let (scheme, path) = match location.find("://") {
+        Some(index) => (&location[..index], &location[index + 3..]),
+        None => return Ok(Some(PathBuf::from(location))),
+    };
+    let is_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    match scheme.to_lowercase().as_str() {
+        "http" | "https" => Ok(None),
+        "file" => Ok(Some(PathBuf::from(path))),
+        // a path that happens to contain "://"
+        _ if !is_scheme => Ok(Some(PathBuf::from(location))),
+        _ => Err(UnsupportedSchemeError {
+            scheme: scheme.to_string(),
+        }),
+    }
+}
+
+/// Returns the contents of the file at the given url or local path
+fn fetch(target_url: &str) -> Result<String, Box<dyn Error>> {
+    if let Some(path) = local_path(target_url)? {
+        if !path.is_file() {
+            return Err(Box::new(PackageNotFoundError));
+        }
+        return Ok(fs::read_to_string(path)?);
+    }
+
+    let response = reqwest::get(target_url)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(Box::new(PackageNotFoundError));
+    }
+
+    Ok(response.error_for_status()?.text()?)
+}
+
+/// Checks that the detached minisign signature of the contents
+/// was made with any of the trusted keys, and that it was made for the
+/// requested file, whose path inside of the repo (like *linux/python/metadata.yml*)
+/// must be the `file:` of its trusted comment.
+///
+/// # Errors
+/// The function will return an error if the signature is malformed,
+/// if none of the keys can verify it or if it was made for another file.
+pub fn verify_signature(
+    contents: &str,
+    signature: &str,
+    file: &str,
+    trusted_keys: &[PublicKey],
+) -> Result<(), Box<dyn Error>> {
+    let signature = match Signature::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return Err(Box::new(SignatureVerificationError)),
+    };
+    let verified = trusted_keys
+        .iter()
+        .any(|key| key.verify(contents.as_bytes(), &signature, false).is_ok());
+    if !verified {
+        return Err(Box::new(SignatureVerificationError));
+    }
+    // otherwise any signed file of the repo could be served in place of another one
+    let signed_file = signature
+        .trusted_comment()
+        .split('\t')
+        .find_map(|field| field.strip_prefix("file:"));
+    if signed_file != Some(file) {
+        return Err(Box::new(SignatureVerificationError));
+    }
+
+    Ok(())
+}
+
+/// Reads every minisign public key (*.pub files) stored in the given directory
+///
+/// # Errors
+/// The function will return an error if the directory or any of
+/// the keys inside of it can't be read.
+pub fn read_trusted_keys(keys_dir: &Path) -> Result<Vec<PublicKey>, Box<dyn Error>> {
+    let mut keys = Vec::new();
+    if !keys_dir.is_dir() {
+        return Ok(keys);
+    }
+    for entry in fs::read_dir(keys_dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pub") {
+            continue;
+        }
+        keys.push(PublicKey::from_file(&path)?);
+    }
+
+    Ok(keys)
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut respone = reqwest::get(&target_url)?;
+    // key pair and signature of the word "test" as linux/node/metadata.yml,
+    // in the format of minisign
+    static PUBLIC_KEY: &str = "untrusted comment: minisign public key
+RWRfQO/qrAXenMrzz5mBeWPkuogD5Iof0Mwpunq68QMf4i+KfJnwFDwX
+";
+
+    static SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURfQO/qrAXenOd7LGjtlxCTUAZxclyGOgGOClOb99lZScumtB8O1FYXyZdpa7Orc3MjZpTJFymm65ZfaccyWfw50JqldulYQgw=
+trusted comment: timestamp:1556193335\tfile:linux/node/metadata.yml
+H2FcLHp9eXVxixm296vQf9HLJiO/m6i0ZuyklPK26nxvCj8J1PmltGwAQuaOYXDrBXDxEVCLL/F4EAHWaC/WAQ==
+";
+
+    static SIGNED_FILE: &str = "linux/node/metadata.yml";
+
+    fn trusted_keys() -> Vec<PublicKey> {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("arcanum.pub"), PUBLIC_KEY).unwrap();
+        fs::write(dir.path().join("README"), "not a key").unwrap();
+        read_trusted_keys(dir.path()).unwrap()
+    }
+
+    #[test]
+    fn default_repo_is_used_without_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos = RepoList::load(&dir.path().join("repos.yml"), "linux", dir.path()).unwrap();
+
+        let names: Vec<&str> = repos.iter().map(|repo| repo.get_name()).collect();
+        assert_eq!(names, vec![DEFAULT_REPO_NAME]);
+    }
+
+    #[test]
+    fn repos_are_sorted_by_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repos.yml");
+        let mut repos = RepoList::load(&path, "linux", dir.path()).unwrap();
+        repos
+            .add("internal", "https://tools.example.com/", 10, "linux")
+            .unwrap();
+        repos
+            .add("mirror", "https://mirror.example.com/", 0, "linux")
+            .unwrap();
+        assert!(repos
+            .add("mirror", "https://other.example.com/", 0, "linux")
+            .is_err());
+        for name in &["../escape", "a/b", "a\\b", "..", ".", ""] {
+            let err = repos.add(name, "https://other.example.com/", 0, "linux");
+            assert!(err.err().unwrap().is::<InvalidRepoNameError>());
+        }
+        repos.save(&path).unwrap();
+
+        let repos = RepoList::load(&path, "linux", dir.path()).unwrap();
+        let names: Vec<&str> = repos.iter().map(|repo| repo.get_name()).collect();
+        assert_eq!(names, vec!["internal", DEFAULT_REPO_NAME, "mirror"]);
+        assert_eq!(repos.get("internal").unwrap().get_priority(), 10);
+        assert_eq!(
+            repos.get("internal").unwrap().get_base_url(),
+            "https://tools.example.com/"
+        );
+    }
+
+    #[test]
+    fn repos_can_be_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repos = RepoList::load(&dir.path().join("repos.yml"), "linux", dir.path()).unwrap();
+
+        repos.remove(DEFAULT_REPO_NAME).unwrap();
+        assert!(repos.iter().next().is_none());
+        assert!(repos.remove(DEFAULT_REPO_NAME).is_err());
+    }
+
+    #[test]
+    fn local_paths_are_recognized() {
+        let path = |location| local_path(location).unwrap().unwrap();
+        assert_eq!(path("file:///srv/repo/"), PathBuf::from("/srv/repo/"));
+        assert_eq!(path("/srv/repo"), PathBuf::from("/srv/repo"));
+        assert_eq!(path("repos/local"), PathBuf::from("repos/local"));
+        assert!(local_path("https://example.com/repo").unwrap().is_none());
+        assert!(local_path("HTTP://example.com/repo").unwrap().is_none());
+
+        // other schemes are not taken for relative paths
+        assert!(local_path("ftp://example.com/repo").is_err());
+        assert!(local_path("htps://example.com/repo").is_err());
+        let mut repos = RepoList { repos: Vec::new() };
+        assert!(repos
+            .add("mirror", "ftp://example.com/repo", 0, "linux")
+            .is_err());
+    }
+
+    #[test]
+    fn local_repos_are_searched_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for (repo, program) in [("first", "node"), ("second", "node"), ("second", "ruby")].iter() {
+            let program_dir = dir.path().join(repo).join("linux").join(program);
+            fs::create_dir_all(&program_dir).unwrap();
+            fs::write(program_dir.join("metadata.yml"), format!("repo: {}", repo)).unwrap();
+        }
+        let first = format!("file://{}", dir.path().join("first").display());
+        let second = dir.path().join("second").display().to_string();
+        let mut repos = RepoList { repos: Vec::new() };
+        repos.add("second", &second, 0, "linux").unwrap();
+        repos.add("first", &first, 1, "linux").unwrap();
+
+        assert_eq!(repos.find("node").unwrap().get_name(), "first");
+        assert_eq!(repos.find("ruby").unwrap().get_name(), "second");
+        assert_eq!(
+            repos
+                .find("ruby")
+                .unwrap()
+                .get_program_metadata("ruby")
+                .unwrap(),
+            "repo: second"
+        );
+        let missing = repos.find("python").err().unwrap();
+        assert!(missing.is::<PackageNotFoundError>());
+    }
+
+    #[test]
+    fn offline_repos_only_serve_cached_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repos = RepoList { repos: Vec::new() };
+        repos
+            .add("remote", "https://sage.invalid/", 0, "linux")
+            .unwrap();
+        repos.set_cache(dir.path(), Duration::from_secs(0), true);
+        let cache = Cache::new(&dir.path().join("remote"), Duration::from_secs(0), true);
+        cache.put("linux/node/metadata.yml", "name: node").unwrap();
+
+        assert_eq!(repos.find("node").unwrap().get_name(), "remote");
+        let missing = repos.find("ruby").err().unwrap();
+        assert!(missing.is::<NotCachedError>());
+    }
+
+    #[test]
+    fn cached_files_are_checked_against_the_trusted_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new("remote", "https://sage.invalid/", "linux");
+        let cache = || Cache::new(dir.path(), Duration::from_secs(0), true);
+        repo.set_cache(cache());
+        // cached before the repo had any trusted key
+        cache().put("linux/node/metadata.yml", "test").unwrap();
+        assert_eq!(repo.get_program_metadata("node").unwrap(), "test");
+
+        repo.set_trusted_keys(trusted_keys());
+        let err = repo.get_program_metadata("node").err().unwrap();
+        assert!(err.is::<SignatureVerificationError>());
+
+        cache()
+            .put("linux/node/metadata.yml.minisig", SIGNATURE)
+            .unwrap();
+        assert_eq!(repo.get_program_metadata("node").unwrap(), "test");
+
+        cache().put("linux/node/metadata.yml", "Test").unwrap();
+        assert!(repo.get_program_metadata("node").is_err());
+    }
+
+    #[test]
+    fn program_names_can_not_leave_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let mut repo = Repo::new("remote", "https://sage.invalid/", "linux");
+        repo.set_cache(Cache::new(&cache_dir, Duration::from_secs(0), true));
+        fs::write(dir.path().join("metadata.yml"), "outside").unwrap();
+
+        for name in &["../..", "../../../metadata.yml", "linux/node", "node/", ""] {
+            let err = repo.get_program_metadata(name).err().unwrap();
+            assert!(err.is::<InvalidPackageNameError>());
+        }
+        let err = repo.get_program_package("node", "../../x").err().unwrap();
+        assert!(err.is::<InvalidPackageNameError>());
+    }
+
+    #[test]
+    fn signed_files_are_only_accepted_under_their_own_name() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in &["linux/node/metadata.yml", "linux/deno/metadata.yml"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "test").unwrap();
+            fs::write(path.with_extension("yml.minisig"), SIGNATURE).unwrap();
+        }
+        let mut repo = Repo::new("local", dir.path().to_str().unwrap(), "linux");
+        repo.set_trusted_keys(trusted_keys());
+
+        assert_eq!(repo.get_program_metadata("node").unwrap(), "test");
+        let err = repo.get_program_metadata("deno").err().unwrap();
+        assert!(err.is::<SignatureVerificationError>());
+    }
+
+    #[test]
+    fn keys_are_read_from_the_store() {
+        assert_eq!(trusted_keys().len(), 1);
+        assert!(read_trusted_keys(Path::new("/nonexistent/keys"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        assert!(verify_signature("test", SIGNATURE, SIGNED_FILE, &trusted_keys()).is_ok());
+    }
 
-        Ok(respone.text()?)
+    #[test]
+    fn tampered_contents_are_rejected() {
+        assert!(verify_signature("Test", SIGNATURE, SIGNED_FILE, &trusted_keys()).is_err());
+        assert!(verify_signature("test", "garbage", SIGNED_FILE, &trusted_keys()).is_err());
+        assert!(verify_signature("test", SIGNATURE, SIGNED_FILE, &[]).is_err());
+        // a valid signature, but of another file
+        let other_file = "linux/node/node_12.0.yml";
+        assert!(verify_signature("test", SIGNATURE, other_file, &trusted_keys()).is_err());
     }
 }
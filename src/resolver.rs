@@ -0,0 +1,238 @@
+use crate::errors::{DependencyConflictError, DependencyCycleError};
+use crate::versions::VersionReq;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Source of the information that the resolver needs about the packages
+pub trait PackageSource {
+    /// Returns every version of the package that can be installed
+    fn available_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Returns every version of the package that is already installed
+    fn installed_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>>;
+
+    /// Returns the dependencies of a version of the package,
+    /// each one with its version requirement
+    fn dependencies(
+        &self,
+        name: &str,
+        version: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>>;
+}
+
+/// Computes the plan for installing a version of a package: every dependency
+/// that is not installed yet, in the order in which they have to be installed,
+/// followed by the package itself. Installed versions that meet the requirements
+/// are preferred, and otherwise the newest available version that meets them is used.
+///
+/// # Errors
+/// The function will return an error if the dependencies form a cycle,
+/// if no version of a dependency meets every requirement on it,
+/// or if a requirement is not valid.
+/// Check out the documentation for `PackageSource` to find out
+/// other reasons for this function to fail.
+pub fn resolve<S: PackageSource>(
+    source: &S,
+    name: &str,
+    version: &str,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut resolver = Resolver {
+        source,
+        chosen: HashMap::new(),
+        requirements: HashMap::new(),
+        stack: Vec::new(),
+        plan: Vec::new(),
+    };
+    resolver
+        .chosen
+        .insert(name.to_string(), version.to_string());
+    resolver.visit(name, version)?;
+
+    Ok(resolver.plan)
+}
+
+struct Resolver<'a, S: PackageSource> {
+    source: &'a S,
+    chosen: HashMap<String, String>, // version picked for every package seen so far
+    requirements: HashMap<String, Vec<String>>, // what was asked of each package, and by whom
+    stack: Vec<String>,              // packages whose dependencies are being resolved
+    plan: Vec<(String, String)>,
+}
+
+impl<'a, S: PackageSource> Resolver<'a, S> {
+    /// Resolves the dependencies of a package before adding it to the plan
+    fn visit(&mut self, name: &str, version: &str) -> Result<(), Box<dyn Error>> {
+        self.stack.push(name.to_string());
+        for (dependency, req_str) in self.source.dependencies(name, version)? {
+            let req = VersionReq::parse(&req_str)?;
+            self.requirements
+                .entry(dependency.clone())
+                .or_default()
+                .push(format!("{} (from {}@{})", req_str, name, version));
+
+            if let Some(start) = self.stack.iter().position(|pkg| *pkg == dependency) {
+                let mut cycle = self.stack[start..].to_vec();
+                cycle.push(dependency);
+                return Err(Box::new(DependencyCycleError { cycle }));
+            }
+            if let Some(chosen) = self.chosen.get(&dependency) {
+                if !req.matches(chosen) {
+                    return Err(self.conflict(&dependency));
+                }
+                continue;
+            }
+
+            let installed = self.source.installed_versions(&dependency)?;
+            if let Some(version) = req.newest_match(installed.iter().map(String::as_str)) {
+                // already installed, along with its own dependencies
+                self.chosen.insert(dependency, version.to_string());
+                continue;
+            }
+            let available = self.source.available_versions(&dependency)?;
+            let version = match req.newest_match(available.iter().map(String::as_str)) {
+                Some(version) => version.to_string(),
+                None => return Err(self.conflict(&dependency)),
+            };
+            self.chosen.insert(dependency.clone(), version.clone());
+            self.visit(&dependency, &version)?;
+        }
+        self.stack.pop();
+        self.plan.push((name.to_string(), version.to_string()));
+
+        Ok(())
+    }
+
+    fn conflict(&self, name: &str) -> Box<dyn Error> {
+        Box::new(DependencyConflictError {
+            name: name.to_string(),
+            requirements: self.requirements.get(name).cloned().unwrap_or_default(),
+        })
+    }
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeSource {
+        available: HashMap<&'static str, Vec<&'static str>>,
+        installed: HashMap<&'static str, Vec<&'static str>>,
+        dependencies: HashMap<String, Vec<(&'static str, &'static str)>>,
+    }
+
+    impl FakeSource {
+        fn add(
+            &mut self,
+            name: &'static str,
+            version: &'static str,
+            deps: &[(&'static str, &'static str)],
+        ) {
+            self.available.entry(name).or_default().push(version);
+            self.dependencies
+                .insert(format!("{}@{}", name, version), deps.to_vec());
+        }
+    }
+
+    impl PackageSource for FakeSource {
+        fn available_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            let versions = self.available.get(name).cloned().unwrap_or_default();
+            Ok(versions.into_iter().map(String::from).collect())
+        }
+
+        fn installed_versions(&self, name: &str) -> Result<Vec<String>, Box<dyn Error>> {
+            let versions = self.installed.get(name).cloned().unwrap_or_default();
+            Ok(versions.into_iter().map(String::from).collect())
+        }
+
+        fn dependencies(
+            &self,
+            name: &str,
+            version: &str,
+        ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+            let deps = self
+                .dependencies
+                .get(&format!("{}@{}", name, version))
+                .cloned()
+                .unwrap_or_default();
+            Ok(deps
+                .into_iter()
+                .map(|(name, req)| (name.to_string(), req.to_string()))
+                .collect())
+        }
+    }
+
+    fn plan(steps: &[(&str, &str)]) -> Vec<(String, String)> {
+        steps
+            .iter()
+            .map(|(name, version)| (name.to_string(), version.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_come_first() {
+        let mut source = FakeSource::default();
+        source.add("python", "3.8.0", &[("openssl", ">=1.1"), ("zlib", "*")]);
+        source.add("openssl", "1.0.2", &[]);
+        source.add("openssl", "1.1.1", &[("zlib", "^1.2")]);
+        source.add("zlib", "1.2.11", &[]);
+        source.add("zlib", "2.0.0", &[]);
+
+        assert_eq!(
+            resolve(&source, "python", "3.8.0").unwrap(),
+            plan(&[
+                ("zlib", "1.2.11"),
+                ("openssl", "1.1.1"),
+                ("python", "3.8.0")
+            ])
+        );
+    }
+
+    #[test]
+    fn installed_dependencies_are_reused() {
+        let mut source = FakeSource::default();
+        source.add("python", "3.8.0", &[("openssl", ">=1.1")]);
+        source.add("openssl", "1.1.1", &[]);
+        source.add("openssl", "1.1.1d", &[]);
+        source.installed.insert("openssl", vec!["1.1.1"]);
+
+        assert_eq!(
+            resolve(&source, "python", "3.8.0").unwrap(),
+            plan(&[("python", "3.8.0")])
+        );
+    }
+
+    #[test]
+    fn cycles_are_detected() {
+        let mut source = FakeSource::default();
+        source.add("a", "1.0", &[("b", "*")]);
+        source.add("b", "1.0", &[("c", "*")]);
+        source.add("c", "1.0", &[("b", "*")]);
+
+        let err = resolve(&source, "a", "1.0").err().unwrap();
+        let cycle = &err.downcast_ref::<DependencyCycleError>().unwrap().cycle;
+        assert_eq!(cycle, &vec!["b", "c", "b"]);
+    }
+
+    #[test]
+    fn conflicts_are_detected() {
+        let mut source = FakeSource::default();
+        source.add("app", "1.0", &[("zlib", "^1.2"), ("tool", "*")]);
+        source.add("tool", "1.0", &[("zlib", ">=2")]);
+        source.add("zlib", "1.2.11", &[]);
+        source.add("zlib", "2.0.0", &[]);
+
+        let err = resolve(&source, "app", "1.0").err().unwrap();
+        let conflict = err.downcast_ref::<DependencyConflictError>().unwrap();
+        assert_eq!(conflict.name, "zlib");
+        assert_eq!(conflict.requirements.len(), 2);
+
+        source.add("lonely", "1.0", &[("missing", "*")]);
+        assert!(resolve(&source, "lonely", "1.0").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+extern crate yaml_rust;
+
+use crate::yml_parser::scalar_to_string;
+
+use yaml_rust::Yaml;
+
+/// Entry of a package in the index of a repository
+pub struct CatalogEntry {
+    pub repo: String,
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub latest: String,
+}
+
+impl CatalogEntry {
+    /// Reads an entry of the index of the given repository.
+    /// Entries without a name are not valid, the rest of the fields are optional.
+    /// Fields that yaml reads as numbers, like a latest version of *3.9*, are kept as written.
+    pub fn from_yaml(repo: &str, entry: &Yaml) -> Option<CatalogEntry> {
+        let name = scalar_to_string(&entry["name"])?;
+        let tags = match entry["tags"].as_vec() {
+            Some(tags) => tags.iter().filter_map(scalar_to_string).collect(),
+            None => Vec::new(),
+        };
+
+        Some(CatalogEntry {
+            repo: repo.to_string(),
+            name,
+            description: scalar_to_string(&entry["description"]).unwrap_or_default(),
+            tags,
+            latest: scalar_to_string(&entry["latest"]).unwrap_or_default(),
+        })
+    }
+}
+
+/// Returns the entries that match the search term, best matches first.
+/// Names, tags and descriptions are matched by substring, and names and tags
+/// are matched fuzzily as well, so typos and abbreviations still find the package.
+pub fn search<'a>(entries: &'a [CatalogEntry], term: &str) -> Vec<&'a CatalogEntry> {
+    let term = term.to_lowercase();
+    let mut matches: Vec<(usize, &CatalogEntry)> = entries
+        .iter()
+        .filter_map(|entry| match_score(entry, &term).map(|score| (score, entry)))
+        .collect();
+    matches.sort_by(|(a_score, a), (b_score, b)| (a_score, &a.name).cmp(&(b_score, &b.name)));
+
+    matches.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Scores how well the entry matches the (lowercase) term, the lower the better
+fn match_score(entry: &CatalogEntry, term: &str) -> Option<usize> {
+    let name = entry.name.to_lowercase();
+    let tags: Vec<String> = entry.tags.iter().map(|tag| tag.to_lowercase()).collect();
+
+    if name == term {
+        Some(0)
+    } else if name.starts_with(term) {
+        Some(1)
+    } else if name.contains(term) {
+        Some(2)
+    } else if tags.iter().any(|tag| tag.contains(term)) {
+        Some(3)
+    } else if entry.description.to_lowercase().contains(term) {
+        Some(4)
+    } else if is_subsequence(term, &name) {
+        Some(5)
+    } else {
+        // allow roughly one typo every three characters
+        let max_distance = std::cmp::max(1, term.chars().count() / 3);
+        std::iter::once(&name)
+            .chain(tags.iter())
+            .map(|word| edit_distance(term, word))
+            .filter(|distance| *distance <= max_distance)
+            .min()
+            .map(|distance| 5 + distance)
+    }
+}
+
+/// Checks if every character of the term appears in the word, in order
+fn is_subsequence(term: &str, word: &str) -> bool {
+    let mut chars = word.chars();
+    term.chars().all(|c| chars.any(|other| other == c))
+}
+
+/// Returns the Levenshtein distance between two words
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, description: &str, tags: &[&str]) -> CatalogEntry {
+        CatalogEntry {
+            repo: String::from("Arcanum"),
+            name: name.to_string(),
+            description: description.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            latest: String::from("1.0.0"),
+        }
+    }
+
+    fn names(matches: Vec<&CatalogEntry>) -> Vec<&str> {
+        matches.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn substring_matches_come_first() {
+        let entries = vec![
+            entry("pypy", "A fast python implementation", &[]),
+            entry("python", "A programming language", &["language"]),
+            entry("ruby", "A programming language", &["language"]),
+            entry("node", "A javascript runtime", &["javascript"]),
+        ];
+
+        assert_eq!(names(search(&entries, "python")), vec!["python", "pypy"]);
+        assert_eq!(names(search(&entries, "Lang")), vec!["python", "ruby"]);
+        assert!(search(&entries, "haskell").is_empty());
+    }
+
+    #[test]
+    fn fuzzy_matches_are_found() {
+        let entries = vec![entry("python", "", &[]), entry("node", "", &["javascript"])];
+
+        assert_eq!(names(search(&entries, "pyhton")), vec!["python"]);
+        assert_eq!(names(search(&entries, "pthn")), vec!["python"]);
+        assert_eq!(names(search(&entries, "javscript")), vec!["node"]);
+    }
+
+    #[test]
+    fn numeric_fields_are_read() {
+        let index = yaml_rust::YamlLoader::load_from_str(
+            "- name: python\n  latest: 3.9\n  tags: [3, language]\n- name: node\n  latest: 12\n",
+        )
+        .unwrap();
+        let entries: Vec<CatalogEntry> = index[0]
+            .as_vec()
+            .unwrap()
+            .iter()
+            .filter_map(|entry| CatalogEntry::from_yaml("Arcanum", entry))
+            .collect();
+
+        assert_eq!(entries[0].latest, "3.9");
+        assert_eq!(entries[0].tags, vec!["3", "language"]);
+        assert_eq!(entries[1].latest, "12");
+    }
+
+    #[test]
+    fn edit_distance_counts_changes() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "node"), 4);
+        assert_eq!(edit_distance("node", "node"), 0);
+    }
+}
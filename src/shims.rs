@@ -0,0 +1,225 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Returns the names of the executables exposed by an installed version
+/// of a package, which are the executable files inside of its *bin* directory,
+/// or the ones at its top level when it doesn't have one
+pub fn exposed_executables(root: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let bin = root.join("bin");
+    let dir = if bin.is_dir() {
+        bin
+    } else {
+        root.to_path_buf()
+    };
+
+    let mut executables = Vec::new();
+    if !dir.is_dir() {
+        return Ok(executables);
+    }
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        // follow symlinks here, like python3 -> python3.8
+        if path.is_file() && is_executable(&path) {
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                executables.push(name.to_string());
+            }
+        }
+    }
+    executables.sort();
+
+    Ok(executables)
+}
+
+/// Creates a shim in the bin directory for every executable exposed by the
+/// active version of a package, replacing the shims that the package had before.
+/// The shims go through the *current* pointer of the package, so they always
+/// launch the active version. Executables whose name is already taken by
+/// a shim of another package are skipped, and their names are returned.
+///
+/// # Errors
+/// Check out the documentation for `exposed_executables` and `unlink_shims`
+/// to find out the reasons for this function to fail.
+pub fn link_shims(
+    bin_dir: &Path,
+    install_dir: &Path,
+    name: &str,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    unlink_shims(bin_dir, install_dir, name)?;
+
+    let current = install_dir.join(name).join("current");
+    let exposed_dir = if current.join("bin").is_dir() {
+        current.join("bin")
+    } else {
+        current.clone()
+    };
+
+    let mut conflicts = Vec::new();
+    for executable in exposed_executables(&current)? {
+        let shim = shim_path(bin_dir, &executable);
+        if fs::symlink_metadata(&shim).is_ok() {
+            conflicts.push(executable);
+            continue;
+        }
+        create_shim(&exposed_dir.join(&executable), &shim)?;
+    }
+
+    Ok(conflicts)
+}
+
+/// Removes every shim that launches an executable of the package
+///
+/// # Errors
+/// Check out the documentation for `std::fs::read_dir` and `std::fs::remove_file`.
+pub fn unlink_shims(bin_dir: &Path, install_dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    if !bin_dir.is_dir() {
+        return Ok(());
+    }
+    let package_dir = install_dir.join(name);
+    for entry in fs::read_dir(bin_dir)? {
+        let shim = entry?.path();
+        match shim_target(&shim) {
+            Some(target) if target.starts_with(&package_dir) => fs::remove_file(&shim)?,
+            _ => continue,
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the shell snippet that puts the bin directory on the PATH.
+/// Bash, zsh and fish are supported.
+pub fn path_snippet(shell: &str, bin_dir: &Path) -> Option<String> {
+    let bin_dir = bin_dir.display();
+    match shell {
+        "bash" | "zsh" => Some(format!("export PATH=\"{}:$PATH\"", bin_dir)),
+        "fish" => Some(format!("set -gx PATH \"{}\" $PATH", bin_dir)),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ["exe", "bat", "cmd"].contains(&ext.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+// On unix a shim is a plain symlink to the executable
+
+#[cfg(unix)]
+fn shim_path(bin_dir: &Path, executable: &str) -> PathBuf {
+    bin_dir.join(executable)
+}
+
+#[cfg(unix)]
+fn create_shim(target: &Path, shim: &Path) -> Result<(), Box<dyn Error>> {
+    std::os::unix::fs::symlink(target, shim)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shim_target(shim: &Path) -> Option<PathBuf> {
+    fs::read_link(shim).ok()
+}
+
+// Elsewhere it is a small launcher script, since symlinks need special privileges
+
+#[cfg(not(unix))]
+fn shim_path(bin_dir: &Path, executable: &str) -> PathBuf {
+    let stem = Path::new(executable).file_stem().unwrap_or_default();
+    bin_dir.join(stem).with_extension("cmd")
+}
+
+#[cfg(not(unix))]
+fn create_shim(target: &Path, shim: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(shim, format!("@\"{}\" %*\r\n", target.display()))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn shim_target(shim: &Path) -> Option<PathBuf> {
+    let contents = fs::read_to_string(shim).ok()?;
+    let target = contents.trim_start_matches('@').split('"').nth(1)?;
+    Some(PathBuf::from(target))
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::install_utils::{activate_version, version_dir};
+    use std::os::unix::fs::PermissionsExt;
+
+    fn install_executable(root: &Path, file: &str) {
+        let path = root.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn only_executables_are_exposed() {
+        let root = tempfile::tempdir().unwrap();
+        install_executable(root.path(), "bin/python3");
+        fs::write(root.path().join("bin/README"), "docs").unwrap();
+
+        assert_eq!(exposed_executables(root.path()).unwrap(), vec!["python3"]);
+    }
+
+    #[test]
+    fn shims_follow_the_active_version() {
+        let sage_home = tempfile::tempdir().unwrap();
+        let install_dir = sage_home.path().join("programs");
+        let bin_dir = sage_home.path().join("bin");
+        fs::create_dir_all(&bin_dir).unwrap();
+        install_executable(&version_dir(&install_dir, "python", "3.8.0"), "bin/python3");
+        install_executable(&version_dir(&install_dir, "ruby", "2.6.0"), "bin/ruby");
+        activate_version(&install_dir, "python", "3.8.0").unwrap();
+        activate_version(&install_dir, "ruby", "2.6.0").unwrap();
+
+        assert!(link_shims(&bin_dir, &install_dir, "python")
+            .unwrap()
+            .is_empty());
+        assert!(link_shims(&bin_dir, &install_dir, "ruby")
+            .unwrap()
+            .is_empty());
+        assert!(bin_dir.join("python3").is_file());
+        assert_eq!(
+            fs::read_link(bin_dir.join("python3")).unwrap(),
+            install_dir.join("python/current/bin/python3")
+        );
+
+        unlink_shims(&bin_dir, &install_dir, "python").unwrap();
+        assert!(fs::symlink_metadata(bin_dir.join("python3")).is_err());
+        assert!(bin_dir.join("ruby").is_file());
+    }
+
+    #[test]
+    fn snippets_are_shell_specific() {
+        let bin_dir = Path::new("/home/user/.sage/bin");
+
+        assert_eq!(
+            path_snippet("bash", bin_dir).unwrap(),
+            "export PATH=\"/home/user/.sage/bin:$PATH\""
+        );
+        assert_eq!(
+            path_snippet("fish", bin_dir).unwrap(),
+            "set -gx PATH \"/home/user/.sage/bin\" $PATH"
+        );
+        assert!(path_snippet("tcsh", bin_dir).is_none());
+    }
+}
@@ -0,0 +1,368 @@
+use crate::errors::InvalidVersionReqError;
+
+use std::cmp::Ordering;
+
+/// Compares two version strings, like *3.8.0* and *3.10.1*.
+/// The versions are split into their dot-separated parts, which are compared
+/// numerically when both of them are numbers, and alphabetically otherwise.
+/// A version with more parts is considered newer when the rest is equal.
+/// Whatever follows a `-`, like in *3.9.0-rc1*, makes it a pre-release,
+/// which is older than the version without it, and so do the numbered
+/// `a`, `b`, `rc` and `dev` suffixes of Python, like in *3.9.0rc1* or *3.9.0.dev1*.
+/// Other letters mark later releases instead, like *1.1.1g*.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a_release, a_pre) = split_pre_release(a);
+    let (b_release, b_pre) = split_pre_release(b);
+    match compare_parts_of(a_release, b_release) {
+        Ordering::Equal => match (a_pre, b_pre) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(a_pre), Some(b_pre)) => compare_parts_of(a_pre, b_pre),
+        },
+        ordering => ordering,
+    }
+}
+
+/// Checks if the version is a pre-release, like *3.9.0-rc1*
+fn is_pre_release(version: &str) -> bool {
+    split_pre_release(version).1.is_some()
+}
+
+/// Splits a version into its release and its pre-release, if it has one
+fn split_pre_release(version: &str) -> (&str, Option<&str>) {
+    if let Some(index) = version.find('-') {
+        return (&version[..index], Some(&version[index + 1..]));
+    }
+    // the pre-release suffixes of Python, which follow the number of a part
+    // or make up a part on their own, like in 3.9.0rc1 and 3.9.0.dev1
+    let mut start = 0;
+    for part in version.split('.') {
+        let digits = part.chars().take_while(|c| c.is_ascii_digit()).count();
+        if is_pre_release_suffix(&part[digits..]) {
+            let index = start + digits;
+            let release = version[..index].trim_end_matches('.');
+            return (release, Some(&version[index..]));
+        }
+        start += part.len() + 1;
+    }
+
+    (version, None)
+}
+
+/// Checks if a suffix is a numbered pre-release suffix, like *rc1*, *b2* or *dev0*.
+/// Unnumbered letters, like the *g* of *1.1.1g*, are not.
+fn is_pre_release_suffix(suffix: &str) -> bool {
+    ["a", "b", "rc", "dev"]
+        .iter()
+        .any(|tag| match suffix.strip_prefix(tag) {
+            Some(number) => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+            None => false,
+        })
+}
+
+/// Compares the dot-separated parts of two versions, one by one
+fn compare_parts_of(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        let ordering = match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(a_part), Some(b_part)) => compare_parts(a_part, b_part),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Requirement that a version must meet, made of comma-separated comparators
+/// like *>=3.7,<3.9*. Each comparator is one of:
+/// * `*`, which matches every version
+/// * `=3.8.0`, `>3.8`, `>=3.8`, `<3.8` or `<=3.8`, which compare the versions
+/// * `^3.7`, which matches from 3.7 up to (but not including) 4
+/// * `~3.7.2`, which matches from 3.7.2 up to (but not including) 3.8
+/// * `3.8`, which matches every version that starts with those parts, like 3.8.1
+///
+/// Pre-releases only match requirements that name a pre-release themselves,
+/// so that *^3.7* never picks *3.10.0-rc1* over *3.9.0*.
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+enum Comparator {
+    Any,
+    Exact(String),
+    Greater(String),
+    GreaterEq(String),
+    Less(String),
+    LessEq(String),
+    Prefix(String),
+}
+
+impl VersionReq {
+    /// Parses a version requirement
+    ///
+    /// # Errors
+    /// The function will return an error if any of the comparators has no version.
+    pub fn parse(req: &str) -> Result<VersionReq, InvalidVersionReqError> {
+        let mut comparators = Vec::new();
+        for comparator in req.split(',').map(str::trim) {
+            let (op, version) = match comparator.find(|c: char| c.is_ascii_alphanumeric()) {
+                Some(index) => (comparator[..index].trim(), comparator[index..].trim()),
+                None if comparator == "*" || comparator.is_empty() => {
+                    comparators.push(Comparator::Any);
+                    continue;
+                }
+                None => return Err(InvalidVersionReqError),
+            };
+            let version = version.to_string();
+            match op {
+                "" => comparators.push(Comparator::Prefix(version)),
+                "=" | "==" => comparators.push(Comparator::Exact(version)),
+                ">" => comparators.push(Comparator::Greater(version)),
+                ">=" => comparators.push(Comparator::GreaterEq(version)),
+                "<" => comparators.push(Comparator::Less(version)),
+                "<=" => comparators.push(Comparator::LessEq(version)),
+                "^" => {
+                    // the first non-zero part can't change
+                    let parts: Vec<&str> = version.split('.').collect();
+                    let fixed = parts
+                        .iter()
+                        .position(|part| split_number(part).0 != Some(0))
+                        .unwrap_or(parts.len() - 1);
+                    comparators.push(Comparator::Less(bump(&version, fixed)));
+                    comparators.push(Comparator::GreaterEq(version));
+                }
+                "~" => {
+                    // only the last part can change, or the minor one when given
+                    let parts = version.split('.').count();
+                    let fixed = if parts > 1 { 1 } else { 0 };
+                    comparators.push(Comparator::Less(bump(&version, fixed)));
+                    comparators.push(Comparator::GreaterEq(version));
+                }
+                _ => return Err(InvalidVersionReqError),
+            }
+        }
+
+        Ok(VersionReq { comparators })
+    }
+
+    /// Checks if the version meets every comparator of the requirement
+    pub fn matches(&self, version: &str) -> bool {
+        if is_pre_release(version) && !self.names_pre_release() {
+            return false;
+        }
+        self.comparators.iter().all(|comparator| match comparator {
+            Comparator::Any => true,
+            Comparator::Exact(req) => compare_versions(version, req) == Ordering::Equal,
+            Comparator::Greater(req) => compare_versions(version, req) == Ordering::Greater,
+            Comparator::GreaterEq(req) => compare_versions(version, req) != Ordering::Less,
+            Comparator::Less(req) => compare_versions(version, req) == Ordering::Less,
+            Comparator::LessEq(req) => compare_versions(version, req) != Ordering::Greater,
+            Comparator::Prefix(req) => {
+                let mut parts = version.split('.');
+                req.split('.').all(|part| parts.next() == Some(part))
+            }
+        })
+    }
+
+    /// Checks if any of the comparators compares against a pre-release
+    fn names_pre_release(&self) -> bool {
+        self.comparators.iter().any(|comparator| match comparator {
+            Comparator::Any => false,
+            Comparator::Exact(req)
+            | Comparator::Greater(req)
+            | Comparator::GreaterEq(req)
+            | Comparator::Less(req)
+            | Comparator::LessEq(req)
+            | Comparator::Prefix(req) => is_pre_release(req),
+        })
+    }
+
+    /// Returns the newest of the given versions that meets the requirement
+    pub fn newest_match<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        versions: I,
+    ) -> Option<&'a str> {
+        versions
+            .into_iter()
+            .filter(|version| self.matches(version))
+            .max_by(|a, b| compare_versions(a, b))
+    }
+}
+
+/// Returns the version that comes right after every version sharing
+/// its parts up to the given index, like *4* for *3.7.2* and index 0
+fn bump(version: &str, index: usize) -> String {
+    let mut parts: Vec<String> = version
+        .split('.')
+        .take(index + 1)
+        .map(String::from)
+        .collect();
+    let last = parts.last_mut().unwrap();
+    *last = match split_number(last).0 {
+        Some(num) => (num + 1).to_string(),
+        None => format!("{}~", last),
+    };
+
+    parts.join(".")
+}
+
+/// Compares a single part of a version, like *10* against *8* or *1d*
+fn compare_parts(a: &str, b: &str) -> Ordering {
+    // split the leading number from the rest, so that 1 < 1d < 2,
+    // since letters mark the releases that follow the bare number
+    let (a_num, a_rest) = split_number(a);
+    let (b_num, b_rest) = split_number(b);
+    match (a_num, b_num) {
+        (Some(a_num), Some(b_num)) if a_num != b_num => a_num.cmp(&b_num),
+        (Some(_), Some(_)) => a_rest.cmp(b_rest),
+        // numbered words, like the rc2 and rc10 of pre-releases
+        _ => {
+            let (a_word, a_num) = split_trailing_number(a);
+            let (b_word, b_num) = split_trailing_number(b);
+            a_word.cmp(b_word).then(a_num.cmp(&b_num)).then(a.cmp(b))
+        }
+    }
+}
+
+fn split_trailing_number(part: &str) -> (&str, Option<u64>) {
+    let word = part.trim_end_matches(|c: char| c.is_ascii_digit());
+    (word, part[word.len()..].parse().ok())
+}
+
+fn split_number(part: &str) -> (Option<u64>, &str) {
+    let digits = part.chars().take_while(|c| c.is_ascii_digit()).count();
+    match part[..digits].parse() {
+        Ok(num) => (Some(num), &part[digits..]),
+        Err(_) => (None, part),
+    }
+}
+
+// -----------------------
+//       UNIT TESTS
+// -----------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numeric_parts_are_compared_as_numbers() {
+        assert_eq!(compare_versions("3.10.0", "3.8.0"), Ordering::Greater);
+        assert_eq!(compare_versions("3.7.0", "3.8.0"), Ordering::Less);
+        assert_eq!(compare_versions("3.8.0", "3.8.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn longer_versions_are_newer() {
+        assert_eq!(compare_versions("3.8.1", "3.8"), Ordering::Greater);
+        assert_eq!(compare_versions("11", "11.0.2"), Ordering::Less);
+    }
+
+    #[test]
+    fn requirements_compare_versions() {
+        let req = VersionReq::parse(">=3.7, <3.9").unwrap();
+        assert!(req.matches("3.7.0"));
+        assert!(req.matches("3.8.5"));
+        assert!(!req.matches("3.9.0"));
+        assert!(!req.matches("3.6.9"));
+
+        assert!(VersionReq::parse("*").unwrap().matches("0.1"));
+        assert!(VersionReq::parse("=3.8.0").unwrap().matches("3.8.0"));
+        assert!(!VersionReq::parse("=3.8.0").unwrap().matches("3.8.1"));
+        assert!(VersionReq::parse(">=").is_err());
+        assert!(VersionReq::parse("!3.8").is_err());
+    }
+
+    #[test]
+    fn caret_and_tilde_requirements() {
+        let caret = VersionReq::parse("^3.7").unwrap();
+        assert!(caret.matches("3.7.0"));
+        assert!(caret.matches("3.10.1"));
+        assert!(!caret.matches("4.0.0"));
+        assert!(!VersionReq::parse("^0.4.1").unwrap().matches("0.5.0"));
+
+        let tilde = VersionReq::parse("~3.7.2").unwrap();
+        assert!(tilde.matches("3.7.9"));
+        assert!(!tilde.matches("3.7.1"));
+        assert!(!tilde.matches("3.8.0"));
+    }
+
+    #[test]
+    fn bare_versions_match_prefixes() {
+        let req = VersionReq::parse("3.8").unwrap();
+        assert!(req.matches("3.8.0"));
+        assert!(req.matches("3.8"));
+        assert!(!req.matches("3.80.0"));
+        assert_eq!(
+            req.newest_match(vec!["3.7.5", "3.8.0", "3.8.2", "3.9.0"]),
+            Some("3.8.2")
+        );
+    }
+
+    #[test]
+    fn suffixes_are_pre_releases() {
+        assert_eq!(compare_versions("3.9.0-rc1", "3.9.0"), Ordering::Less);
+        assert_eq!(
+            compare_versions("3.9.0-rc2", "3.9.0-rc1"),
+            Ordering::Greater
+        );
+        assert_eq!(compare_versions("3.9.0-rc2", "3.8.9"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.1d", "1.1.1c"), Ordering::Greater);
+    }
+
+    #[test]
+    fn pre_releases_are_only_matched_when_asked_for() {
+        let versions = vec!["3.8.5", "3.9.0", "3.10.0-rc1"];
+        for req in &["^3.7", ">=3.0", "*", "3"] {
+            let req = VersionReq::parse(req).unwrap();
+            assert_eq!(req.newest_match(versions.clone()), Some("3.9.0"));
+        }
+        let exact = VersionReq::parse("=3.10.0-rc1").unwrap();
+        assert_eq!(exact.newest_match(versions.clone()), Some("3.10.0-rc1"));
+        let bare = VersionReq::parse("3.10.0-rc1").unwrap();
+        assert_eq!(bare.newest_match(versions.clone()), Some("3.10.0-rc1"));
+        let range = VersionReq::parse(">=3.10.0-rc1").unwrap();
+        assert_eq!(range.newest_match(versions), Some("3.10.0-rc1"));
+    }
+
+    #[test]
+    fn python_suffixes_are_pre_releases() {
+        assert_eq!(compare_versions("3.9.0rc1", "3.9.0"), Ordering::Less);
+        assert_eq!(compare_versions("3.9.0.dev1", "3.9.0"), Ordering::Less);
+        assert_eq!(compare_versions("3.9.0a1", "3.9.0b2"), Ordering::Less);
+        assert_eq!(compare_versions("3.9.0b2", "3.9.0rc1"), Ordering::Less);
+        assert_eq!(compare_versions("3.9.0rc10", "3.9.0rc2"), Ordering::Greater);
+        assert_eq!(compare_versions("3.9.0rc1", "3.8.9"), Ordering::Greater);
+        assert_eq!(
+            VersionReq::parse("^3.8").unwrap().newest_match(vec![
+                "3.8.9",
+                "3.9.0",
+                "3.10.0rc1",
+                "3.10.0b2"
+            ]),
+            Some("3.9.0")
+        );
+    }
+
+    #[test]
+    fn letters_are_later_releases() {
+        assert_eq!(compare_versions("1.1.1d", "1.1.1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.1g", "1.1.1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.1a", "1.1.1"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.1b", "1.1.1a"), Ordering::Greater);
+        assert_eq!(compare_versions("1.1.1", "1.1.1d"), Ordering::Less);
+        assert_eq!(compare_versions("1.1.2", "1.1.1d"), Ordering::Greater);
+        assert!(!VersionReq::parse("^1.1.1d").unwrap().matches("1.1.1"));
+        assert_eq!(
+            VersionReq::parse("^1.1")
+                .unwrap()
+                .newest_match(vec!["1.1.1", "1.1.1d", "1.1.1-beta"]),
+            Some("1.1.1d")
+        );
+    }
+}
@@ -1,10 +1,12 @@
 extern crate yaml_rust;
 
-use yaml_rust::scanner::ScanError;
+use crate::errors::{EmptyDocumentError, InvalidVersionReqError};
+use crate::install_utils::{BuildOption, InstallStep};
+
 use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 
-// TODO: Look for a different solution other than .clone() for creating a Parser
+use std::error::Error;
 
 /// A parser for reading metadata information about *Arcanum* packages
 pub struct MetadataParser {
@@ -16,6 +18,11 @@ pub struct PackageParser {
     doc: Yaml,
 }
 
+/// A parser for reading the index of every package in a repository
+pub struct IndexParser {
+    doc: Yaml,
+}
+
 /// Enum containing every available installation detail that a package
 /// can have in *Arcanum*
 pub enum InstallInfo {
@@ -28,9 +35,8 @@ pub enum InstallInfo {
 impl MetadataParser {
     /// Creates a new `MetadataParser`, instantly parsing the contents of a
     /// yaml-formatted string
-    pub fn new(str: &str) -> Result<MetadataParser, ScanError> {
-        let docs = YamlLoader::load_from_str(str)?;
-        let doc = docs[0].clone();
+    pub fn new(str: &str) -> Result<MetadataParser, Box<dyn Error>> {
+        let doc = first_document(str)?;
 
         Ok(MetadataParser { doc })
     }
@@ -41,71 +47,231 @@ impl MetadataParser {
         versions.as_vec()
     }
 
+    /// Returns the versions of the package as strings, including the
+    /// ones that yaml reads as numbers, like *1.0*
+    pub fn get_version_names(&self) -> Vec<String> {
+        match self.get_versions() {
+            Some(versions) => versions.iter().filter_map(scalar_to_string).collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// Returns the long term support version of the package
-    pub fn get_lts_version(&self) -> Option<&str> {
-        let lts_version = &self.doc["lts"];
-        lts_version.as_str()
+    pub fn get_lts_version(&self) -> Option<String> {
+        scalar_to_string(&self.doc["lts"])
     }
 
     /// Returns the latest version of the package
-    pub fn get_latest_version(&self) -> Option<&str> {
-        let latest_version = &self.doc["latest"];
-        latest_version.as_str()
+    pub fn get_latest_version(&self) -> Option<String> {
+        scalar_to_string(&self.doc["latest"])
     }
 }
 
 impl PackageParser {
     /// Creates a new `PackageParser`, instantly parsing the contents of a
     /// yaml-formatted string
-    pub fn new(str: &str) -> Result<PackageParser, ScanError> {
-        let docs = YamlLoader::load_from_str(str)?;
-        let doc = docs[0].clone();
+    pub fn new(str: &str) -> Result<PackageParser, Box<dyn Error>> {
+        let doc = first_document(str)?;
 
         Ok(PackageParser { doc })
     }
 
     /// Returns the name of the package
-    pub fn get_name(&self) -> Option<&str> {
-        let name = &self.doc["name"];
-        name.as_str()
+    pub fn get_name(&self) -> Option<String> {
+        scalar_to_string(&self.doc["name"])
     }
 
     /// Returns the version of the package
-    pub fn get_version(&self) -> Option<&str> {
-        let version = &self.doc["version"];
-        version.as_str()
+    pub fn get_version(&self) -> Option<String> {
+        scalar_to_string(&self.doc["version"])
     }
 
     /// Returns the url of the package
-    pub fn get_ulr(&self) -> Option<&str> {
-        let url = &self.doc["url"];
-        url.as_str()
+    pub fn get_ulr(&self) -> Option<String> {
+        scalar_to_string(&self.doc["url"])
     }
 
     /// Returns the file type of the package
-    pub fn get_file_type(&self) -> Option<&str> {
-        let file_type = &self.doc["type"];
-        file_type.as_str()
+    pub fn get_file_type(&self) -> Option<String> {
+        scalar_to_string(&self.doc["type"])
     }
 
     /// Returns the name of the file to be downloaded
-    pub fn get_file_name(&self) -> Option<&str> {
-        let file = &self.doc["file"];
-        file.as_str()
+    pub fn get_file_name(&self) -> Option<String> {
+        scalar_to_string(&self.doc["file"])
+    }
+
+    /// Returns the expected sha256 checksum of the file to be downloaded
+    pub fn get_sha256(&self) -> Option<String> {
+        scalar_to_string(&self.doc["sha256"])
+    }
+
+    /// Returns the expected sha512 checksum of the file to be downloaded
+    pub fn get_sha512(&self) -> Option<String> {
+        scalar_to_string(&self.doc["sha512"])
+    }
+
+    /// Returns the tag of the repository that a git package is cloned at
+    pub fn get_tag(&self) -> Option<String> {
+        scalar_to_string(&self.doc["tag"])
+    }
+
+    /// Returns the commit of the repository that a git package must be at
+    pub fn get_commit(&self) -> Option<String> {
+        scalar_to_string(&self.doc["commit"])
+    }
+
+    /// Returns the dependencies of the package, each one with its version requirement.
+    /// Dependencies without a requirement accept any version.
+    ///
+    /// # Errors
+    /// The function will return an error if a requirement is not a single value,
+    /// like a list or a hash.
+    pub fn get_dependencies(&self) -> Result<Vec<(String, String)>, InvalidVersionReqError> {
+        let dependencies = match self.doc["dependencies"].as_hash() {
+            Some(dependencies) => dependencies,
+            None => return Ok(Vec::new()),
+        };
+        let mut result = Vec::new();
+        for (name, req) in dependencies {
+            let name = match scalar_to_string(name) {
+                Some(name) => name,
+                None => continue,
+            };
+            let req = match req {
+                Yaml::Null => String::from("*"),
+                req => scalar_to_string(req).ok_or(InvalidVersionReqError)?,
+            };
+            result.push((name, req));
+        }
+
+        Ok(result)
     }
 
     /// Analyzes the install info and returns the desired specific info
-    pub fn get_installation_info(&self, desired_info: InstallInfo) -> Option<&str> {
+    pub fn get_installation_info(&self, desired_info: InstallInfo) -> Option<String> {
         let install_details = &self.doc["installation"];
         // decide waht to do based on what the desireed info is
         let info = match desired_info {
             // the install type has been requested
-            InstallInfo::Type => install_details["type"].as_str(),
-            InstallInfo::Target => install_details["target"].as_str(),
+            InstallInfo::Type => scalar_to_string(&install_details["type"]),
+            InstallInfo::Target => scalar_to_string(&install_details["target"]),
         };
         // return the info
         info
     }
+
+    /// Returns the steps of a scripted installation, in the order in which
+    /// they must run. Steps without a command are ignored.
+    pub fn get_install_steps(&self) -> Vec<InstallStep> {
+        let steps = match self.doc["installation"]["steps"].as_vec() {
+            Some(steps) => steps,
+            None => return Vec::new(),
+        };
+        steps
+            .iter()
+            .filter_map(|step| {
+                let command = scalar_to_string(&step["command"])?;
+                let args = match step["args"].as_vec() {
+                    Some(args) => args.iter().filter_map(scalar_to_string).collect(),
+                    None => Vec::new(),
+                };
+                let env = match step["env"].as_hash() {
+                    Some(env) => env
+                        .iter()
+                        .filter_map(|(name, value)| {
+                            Some((scalar_to_string(name)?, scalar_to_string(value)?))
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                };
+
+                Some(InstallStep {
+                    command,
+                    args,
+                    dir: scalar_to_string(&step["dir"]),
+                    env,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the build options of the installation, in the order in which
+    /// they were declared. An option can be given as the flags it adds when
+    /// enabled, which makes it disabled by default, or as a hash with its
+    /// *default* and its *enabled* and *disabled* flags.
+    pub fn get_build_options(&self) -> Vec<BuildOption> {
+        let options = match self.doc["installation"]["options"].as_hash() {
+            Some(options) => options,
+            None => return Vec::new(),
+        };
+        // flags can be a single one or a list of them
+        let flags = |value: &Yaml| match value.as_vec() {
+            Some(flags) => flags.iter().filter_map(scalar_to_string).collect(),
+            None => scalar_to_string(value).into_iter().collect(),
+        };
+        options
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = scalar_to_string(name)?;
+                let option = match value.as_hash() {
+                    Some(_) => BuildOption {
+                        name,
+                        default: value["default"].as_bool().unwrap_or(false),
+                        enabled: flags(&value["enabled"]),
+                        disabled: flags(&value["disabled"]),
+                    },
+                    None => BuildOption {
+                        name,
+                        default: false,
+                        enabled: flags(value),
+                        disabled: Vec::new(),
+                    },
+                };
+                Some(option)
+            })
+            .collect()
+    }
+}
+
+/// Parses a yaml-formatted string, returning its first document
+///
+/// # Errors
+/// The function will return an error if the string is not valid yaml
+/// or if it holds no document at all, like an empty file.
+fn first_document(str: &str) -> Result<Yaml, Box<dyn Error>> {
+    let docs = YamlLoader::load_from_str(str)?;
+    match docs.into_iter().next() {
+        Some(doc) => Ok(doc),
+        None => Err(Box::new(EmptyDocumentError)),
+    }
+}
+
+/// Returns the value of a yaml scalar as a string, the way it was written
+pub fn scalar_to_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(str) | Yaml::Real(str) => Some(str.clone()),
+        Yaml::Integer(int) => Some(int.to_string()),
+        Yaml::Boolean(bool) => Some(bool.to_string()),
+        _ => None,
+    }
+}
+
+impl IndexParser {
+    /// Creates a new `IndexParser`, instantly parsing the contents of a
+    /// yaml-formatted string
+    pub fn new(str: &str) -> Result<IndexParser, Box<dyn Error>> {
+        let doc = first_document(str)?;
+
+        Ok(IndexParser { doc })
+    }
+
+    /// Returns a vector containing the entries of the packages in the index,
+    /// each one with its name, description, tags and latest version
+    pub fn get_packages(&self) -> Option<&Vec<Yaml>> {
+        let packages = &self.doc["packages"];
+        packages.as_vec()
+    }
 }
 
 // -----------------------
@@ -134,6 +300,36 @@ mod tests {
         type: make
     ";
 
+    static TEST_INDEX: &str = "
+    packages:
+        - name: python
+          description: An interpreted, high-level programming language
+          tags: [language, interpreter]
+          latest: 3.8.0
+        - name: node
+          latest: 13.5.0
+    ";
+
+    #[test]
+    fn empty_documents_are_rejected() {
+        assert!(IndexParser::new("").is_err());
+        assert!(IndexParser::new("  \n\n").is_err());
+        assert!(MetadataParser::new("").is_err());
+        assert!(PackageParser::new("# nothing yet").is_err());
+    }
+
+    #[test]
+    fn index_parser_get_packages() {
+        let index_parser = IndexParser::new(TEST_INDEX)
+            .expect("Could not create an index parser with the given string");
+        let packages = index_parser.get_packages().unwrap();
+
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0]["name"].as_str().unwrap(), "python");
+        assert_eq!(packages[0]["tags"][1].as_str().unwrap(), "interpreter");
+        assert_eq!(packages[1]["latest"].as_str().unwrap(), "13.5.0");
+    }
+
     #[test]
     fn meta_parser_get_versions() {
         let meta_parser = match MetadataParser::new(TEST_YML) {
@@ -155,6 +351,25 @@ mod tests {
         assert_eq!(versions[0].as_str().unwrap(), "3.8.0");
     }
 
+    #[test]
+    fn meta_parser_get_numeric_versions() {
+        let meta_parser = MetadataParser::new(
+            "
+            versions: [1.0, \"1.10\", 13.5, 2]
+            lts: 1.0
+            latest: 13.5
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            meta_parser.get_version_names(),
+            vec!["1.0", "1.10", "13.5", "2"]
+        );
+        assert_eq!(meta_parser.get_lts_version().unwrap(), "1.0");
+        assert_eq!(meta_parser.get_latest_version().unwrap(), "13.5");
+    }
+
     #[test]
     fn meta_parser_get_lts() {
         let meta_parser = MetadataParser::new(TEST_YML)
@@ -197,5 +412,171 @@ mod tests {
         assert_eq!(f_type, "tar.xz");
         assert_eq!(file, "Python-3.8.0.tar.xz");
         assert_eq!(install_type, "make");
+        assert_eq!(pack_parser.get_sha256(), None);
+    }
+
+    #[test]
+    fn pack_parser_get_numeric_fields() {
+        let pack_parser = PackageParser::new(
+            "name: ninja\nversion: 1.0\nurl: https://example.com/ninja\n\
+             installation:\n  type: make\n  target: 2020\n",
+        )
+        .unwrap();
+
+        assert_eq!(pack_parser.get_version().unwrap(), "1.0");
+        assert_eq!(
+            pack_parser
+                .get_installation_info(InstallInfo::Target)
+                .unwrap(),
+            "2020"
+        );
+    }
+
+    #[test]
+    fn pack_parser_get_install_steps() {
+        let pack_parser = PackageParser::new(
+            "
+            installation:
+                type: script
+                steps:
+                    - command: ./bootstrap.sh
+                      args: [--prefix, $PREFIX, -j, 4]
+                      dir: build
+                      env:
+                          CFLAGS: -O2
+                    - command: make
+                    - args: [missing-command]
+            ",
+        )
+        .unwrap();
+        let steps = pack_parser.get_install_steps();
+
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].command, "./bootstrap.sh");
+        assert_eq!(steps[0].args, vec!["--prefix", "$PREFIX", "-j", "4"]);
+        assert_eq!(steps[0].dir.as_deref(), Some("build"));
+        assert_eq!(
+            steps[0].env,
+            vec![(String::from("CFLAGS"), String::from("-O2"))]
+        );
+        assert!(steps[1].args.is_empty());
+        assert!(steps[1].dir.is_none());
+    }
+
+    #[test]
+    fn pack_parser_get_build_options() {
+        let pack_parser = PackageParser::new(
+            "
+            installation:
+                type: make
+                options:
+                    ssl:
+                        default: true
+                        enabled: --with-openssl
+                        disabled: [--without-openssl]
+                    optimizations: [--enable-optimizations, --with-lto]
+                    docs: --enable-docs
+            ",
+        )
+        .unwrap();
+        let options = pack_parser.get_build_options();
+
+        assert_eq!(options.len(), 3);
+        assert_eq!(options[0].name, "ssl");
+        assert!(options[0].default);
+        assert_eq!(options[0].enabled, vec!["--with-openssl"]);
+        assert_eq!(options[0].disabled, vec!["--without-openssl"]);
+        assert_eq!(options[1].name, "optimizations");
+        assert!(!options[1].default);
+        assert_eq!(
+            options[1].enabled,
+            vec!["--enable-optimizations", "--with-lto"]
+        );
+        assert!(options[2].disabled.is_empty());
+        assert!(PackageParser::new("type: git")
+            .unwrap()
+            .get_build_options()
+            .is_empty());
+    }
+
+    #[test]
+    fn pack_parser_get_git_revision() {
+        let pack_parser = PackageParser::new(
+            "
+            type: git
+            url: https://github.com/rvillegasm/sage.git
+            tag: 0.1
+            commit: 9fceb02d0ae598e95dc970b74767f19372d61af8
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(pack_parser.get_tag().unwrap(), "0.1");
+        assert_eq!(
+            pack_parser.get_commit().unwrap(),
+            "9fceb02d0ae598e95dc970b74767f19372d61af8"
+        );
+        assert!(PackageParser::new("type: git").unwrap().get_tag().is_none());
+    }
+
+    #[test]
+    fn pack_parser_get_dependencies() {
+        let pack_parser = PackageParser::new(
+            "
+            name: Python
+            dependencies:
+                openssl: '>=1.1.1'
+                zlib: 1.2
+                libffi:
+                ncurses: 6
+            ",
+        )
+        .expect("Could not create a package parser with the given string");
+
+        let pairs = |dependencies: &[(&str, &str)]| -> Vec<(String, String)> {
+            dependencies
+                .iter()
+                .map(|(name, req)| (name.to_string(), req.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            pack_parser.get_dependencies().unwrap(),
+            pairs(&[
+                ("openssl", ">=1.1.1"),
+                ("zlib", "1.2"),
+                ("libffi", "*"),
+                ("ncurses", "6")
+            ])
+        );
+        assert!(PackageParser::new(TEST_PACKAGE)
+            .unwrap()
+            .get_dependencies()
+            .unwrap()
+            .is_empty());
+        assert!(PackageParser::new("dependencies:\n  zlib: [1.2, 1.3]")
+            .unwrap()
+            .get_dependencies()
+            .is_err());
+    }
+
+    #[test]
+    fn pack_parser_get_checksums() {
+        let pack_parser = PackageParser::new(
+            "
+            name: Python
+            sha256: 4a37f39b3a1ef3ab6e9c1e6d1ba7da9cba58b51cf32e2f8d4e8fa5a8eb1d4e29
+            sha512: cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce
+            ",
+        )
+        .expect("Could not create a package parser with the given string");
+
+        assert_eq!(
+            pack_parser.get_sha256().unwrap(),
+            "4a37f39b3a1ef3ab6e9c1e6d1ba7da9cba58b51cf32e2f8d4e8fa5a8eb1d4e29"
+        );
+        assert_eq!(
+            pack_parser.get_sha512().unwrap(),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce"
+        );
     }
 }